@@ -619,6 +619,7 @@ interface ICurveV2CryptoPool {
         function pool_count() external view returns (uint256);
         function pool_list(uint256 index) external view returns (address);
     }
+#[derive(Debug)]
 interface IERC1155 is IERC165 {
     /**
      * @dev Emitted when `value` amount of tokens of type `id` are transferred from `from` to `to` by `operator`.
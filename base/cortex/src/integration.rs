@@ -78,6 +78,8 @@ pub fn decompose_json_pools(id: u64, pools: &Vec<PoolJsonModel>) -> DecomposePoo
                 let new_v2 = PartialV2Pool {
                     chain: id,
                     address: addr,
+                    dex: None,
+                    provenance: None,
                     config: Some(V2Config {
                         name: "".to_string(),
                         fee: fe,
@@ -85,6 +87,7 @@ pub fn decompose_json_pools(id: u64, pools: &Vec<PoolJsonModel>) -> DecomposePoo
                         token1: t1,
                     }),
                     state: None,
+                    synced_at: None,
                 };
                 v2_pools.push(new_v2);
             }
@@ -111,6 +114,8 @@ pub fn decompose_json_pools(id: u64, pools: &Vec<PoolJsonModel>) -> DecomposePoo
                 let new_v3 = PartialV3Pool {
                     chain: id,
                     address: addr,
+                    dex: None,
+                    provenance: None,
                     config: Some(V3Config {
                         name: "".to_string(),
                         fee: fe,
@@ -119,6 +124,8 @@ pub fn decompose_json_pools(id: u64, pools: &Vec<PoolJsonModel>) -> DecomposePoo
                         tick_spacing,
                     }),
                     state: None,
+                    words: None,
+                    synced_at: None,
                 };
                 v3_pools.push(new_v3);
             }
@@ -158,6 +165,8 @@ pub fn decompose_json_pools(id: u64, pools: &Vec<PoolJsonModel>) -> DecomposePoo
                         hooks: hook,
                     }),
                     state: None,
+                    words: None,
+                    synced_at: None,
                 };
                 v4_pools.push(new_v4);
             }
@@ -2,25 +2,33 @@ use std::os::unix;
 
 use alloy::primitives::Address;
 use shape::{
-    d_any::{DexId, V2Fees, V3Fees},
+    d_any::{DexId, PoolProvenance, V2Fees, V3Fees},
     p_config::{V2Config, V3Config, V4Config},
-    p_state::{V2State, V3State},
+    p_state::{SyncPoint, V2State, V3State},
+    p_ticks::PoolWords,
 };
 
 #[derive(Debug)]
 pub struct PartialV2Pool {
     pub chain: u64,
     pub address: Address,
+    pub dex: Option<DexId>,
+    pub provenance: Option<PoolProvenance>,
     pub config: Option<V2Config>,
     pub state: Option<V2State>,
+    pub synced_at: Option<SyncPoint>,
 }
 
 #[derive(Debug)]
 pub struct PartialV3Pool {
     pub chain: u64,
     pub address: Address,
+    pub dex: Option<DexId>,
+    pub provenance: Option<PoolProvenance>,
     pub config: Option<V3Config>,
     pub state: Option<V3State>,
+    pub words: Option<PoolWords>,
+    pub synced_at: Option<SyncPoint>,
 }
 
 #[derive(Debug)]
@@ -29,6 +37,8 @@ pub struct PartialV4Pool {
     pub state_view: Address,
     pub config: Option<V4Config>,
     pub state: Option<V3State>,
+    pub words: Option<PoolWords>,
+    pub synced_at: Option<SyncPoint>,
 }
 
 #[derive(Debug)]
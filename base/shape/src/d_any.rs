@@ -1,4 +1,4 @@
-use alloy::primitives::{Address, aliases::U24};
+use alloy::primitives::{Address, B256, address, aliases::U24, b256};
 
 #[derive(Debug)]
 pub enum AnyDexShape {
@@ -28,6 +28,94 @@ pub enum DexId {
     Unknown,
 }
 
+/// How far a pool is known to belong to the dex it claims
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum PoolProvenance {
+    /// the factory of a configured dex maps the pool tokens (and fee) to this address
+    Trusted,
+    /// claims a configured factory that doesn't know it
+    Untrusted,
+    /// claims a factory that is not one of the configured dexes
+    UnknownDex,
+}
+
+/// Factories of the dexes we can name by chain, a factory address only names the dex on
+/// the chains listed for it
+const KNOWN_FACTORIES: &[(&[u64], Address, DexId)] = &[
+    // uniswap v2, mainnet only
+    (
+        &[1],
+        address!("0x5C69bEe701ef814a2B6a3EDD4B1652CB9cc5aA6f"),
+        DexId::Uniswap,
+    ),
+    // uniswap v3, mainnet, optimism, polygon and arbitrum share the address
+    (
+        &[1, 10, 137, 42161],
+        address!("0x1F98431c8aD98523631AE4a59f267346ea31F984"),
+        DexId::Uniswap,
+    ),
+    // sushiswap v2 / v3, mainnet only
+    (
+        &[1],
+        address!("0xC0AEe478e3658e2610c5F7A4A2E1777cE9e4f2Ac"),
+        DexId::Sushiswap,
+    ),
+    (
+        &[1],
+        address!("0xbACEB8eC6b9355Dfc0269C18bac9d6E2Bdc29C4F"),
+        DexId::Sushiswap,
+    ),
+    // pancake v2, bsc only
+    (
+        &[56],
+        address!("0xcA143Ce32Fe78f1f7019d7d551a6402fC5350c73"),
+        DexId::Pancake,
+    ),
+    // pancake v3
+    (
+        &[1, 56, 8453, 42161],
+        address!("0x0BFbCF9fa4f9C56B0F40a671Ad40E0805A091865"),
+        DexId::Pancake,
+    ),
+];
+
+impl DexId {
+    /// Pair init code hash of the dex v2 factory, for CREATE2 derivation
+    pub fn v2_init_code_hash(&self) -> Option<B256> {
+        match self {
+            DexId::Uniswap => Some(b256!(
+                "0x96e8ac4277198ff8b6f785478aa9a39f403cb768dd02cbee326c3e7da348845f"
+            )),
+            DexId::Sushiswap => Some(b256!(
+                "0xe18a34eb0e04b04f7a0ac29a6e80748dca96319b42c520bfac6b1df1e0f8e40a"
+            )),
+            DexId::Pancake => Some(b256!(
+                "0x00fb7f630766e6a796048ea87d01acd3068e8ff67d078148a3fa3f4a84f69bd5"
+            )),
+            _ => None,
+        }
+    }
+
+    /// Pool init code hash of the dex v3 factory, for CREATE2 derivation
+    pub fn v3_init_code_hash(&self) -> Option<B256> {
+        match self {
+            DexId::Uniswap | DexId::Sushiswap => Some(b256!(
+                "0xe34f199b19b2b4f47f68442619d555527d244f78a3297ea89325f843f87b8b54"
+            )),
+            _ => None,
+        }
+    }
+
+    /// Dex behind a well known factory of the chain, `Unknown` for any other
+    pub fn from_factory(chain_id: u64, factory: Address) -> Self {
+        KNOWN_FACTORIES
+            .iter()
+            .find(|(chains, address, _)| *address == factory && chains.contains(&chain_id))
+            .map(|(_, _, id)| *id)
+            .unwrap_or(DexId::Unknown)
+    }
+}
+
 #[derive(Debug)]
 pub struct FullV2Dex {
     pub chain: u64,
@@ -1,9 +1,11 @@
 use all_sol_types::sol_types::PoolKey;
-use alloy::primitives::{
-    Address, B256,
-    aliases::{I24, U24},
-    hex::encode,
-    keccak256,
+use alloy::{
+    primitives::{
+        Address, B256,
+        aliases::{I24, U24},
+        keccak256,
+    },
+    sol_types::SolValue,
 };
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
@@ -40,7 +42,31 @@ pub struct V4Config {
     pub token1: Address,
 }
 
+impl V2Config {
+    /// CREATE2 address of the pair, `init_code_hash` is the pair init code hash of the
+    /// dex (see `DexId::v2_init_code_hash`)
+    pub fn pool_address(&self, factory: Address, init_code_hash: B256) -> Address {
+        let (token0, token1) = sort_tokens(self.token0, self.token1);
+        let salt = keccak256((token0, token1).abi_encode_packed());
+        factory.create2(salt, init_code_hash)
+    }
+}
+
+impl V3Config {
+    /// CREATE2 address of the pool, `deployer` is the factory for uniswap style dexes
+    pub fn pool_address(&self, deployer: Address, init_code_hash: B256) -> Address {
+        let (token0, token1) = sort_tokens(self.token0, self.token1);
+        let salt = keccak256((token0, token1, self.fee).abi_encode());
+        deployer.create2(salt, init_code_hash)
+    }
+}
+
 impl V4Config {
+    /// `PoolId` of the pool, the keccak of its abi encoded `PoolKey`
+    pub fn pool_id(&self) -> B256 {
+        keccak256(self.to_key().abi_encode())
+    }
+
     pub fn to_key(&self) -> PoolKey {
         PoolKey {
             currency0: self.token0,
@@ -63,3 +89,77 @@ impl From<PoolKey> for V4Config {
         }
     }
 }
+
+fn sort_tokens(a: Address, b: Address) -> (Address, Address) {
+    if a < b { (a, b) } else { (b, a) }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::{address, b256};
+
+    use super::*;
+    use crate::d_any::DexId;
+
+    const USDC: Address = address!("0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48");
+    const WETH: Address = address!("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2");
+
+    #[test]
+    fn uniswap_v2_pair_address() {
+        let config = V2Config {
+            name: "".to_string(),
+            fee: U24::from(3000),
+            // the pair address doesn't depend on the token order
+            token0: WETH,
+            token1: USDC,
+        };
+        let factory = address!("0x5C69bEe701ef814a2B6a3EDD4B1652CB9cc5aA6f");
+        assert_eq!(DexId::from_factory(1, factory), DexId::Uniswap);
+        // the mainnet factory address names nothing on other chains
+        assert_eq!(DexId::from_factory(8453, factory), DexId::Unknown);
+
+        assert_eq!(
+            config.pool_address(factory, DexId::Uniswap.v2_init_code_hash().unwrap()),
+            address!("0xB4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc")
+        );
+    }
+
+    #[test]
+    fn uniswap_v3_pool_address() {
+        let factory = address!("0x1F98431c8aD98523631AE4a59f267346ea31F984");
+        let init_code_hash = DexId::Uniswap.v3_init_code_hash().unwrap();
+        let pool = |fee: u32, tick_spacing: i32| V3Config {
+            name: "".to_string(),
+            fee: U24::from(fee),
+            tick_spacing: I24::try_from(tick_spacing).unwrap(),
+            token0: USDC,
+            token1: WETH,
+        };
+
+        assert_eq!(
+            pool(500, 10).pool_address(factory, init_code_hash),
+            address!("0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640")
+        );
+        assert_eq!(
+            pool(3000, 60).pool_address(factory, init_code_hash),
+            address!("0x8ad599c3A0ff1De082011EFDDc58f1908eb6e6D8")
+        );
+    }
+
+    #[test]
+    fn uniswap_v4_pool_id() {
+        // native ETH / USDC 0.05%
+        let config = V4Config {
+            fee: U24::from(500),
+            tick_spacing: I24::try_from(10).unwrap(),
+            hooks: Address::ZERO,
+            token0: Address::ZERO,
+            token1: USDC,
+        };
+
+        assert_eq!(
+            config.pool_id(),
+            b256!("0x21c67e77068de97969ba93d4aab21826d33ca12bb9f565d8496e8fda8a82ca27")
+        );
+    }
+}
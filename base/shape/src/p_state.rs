@@ -1,21 +1,89 @@
 use all_sol_types::sol_types::IUniswapV2Pair::Swap;
-use alloy::primitives::{Log, U160, aliases::I24};
+use alloy::primitives::{B256, Log, U160, aliases::I24};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum AnyPoolState {
     V2(V2State),
     V3(V3State),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct V3State {
     pub tick: I24,
     pub x96price: U160,
     pub liquidity: u128,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct V2State {
     pub r0: u128,
     pub r1: u128,
 }
+
+/// Point of the chain a pool state reflects: the end of the block it was read at, or
+/// the last log applied on top of it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncPoint {
+    pub block: u64,
+    pub hash: Option<B256>,
+    /// `None` for state read at the block, which already includes every log of it
+    pub log_index: Option<u64>,
+}
+
+impl SyncPoint {
+    pub fn at_block(block: u64, hash: B256) -> Self {
+        Self {
+            block,
+            hash: Some(hash),
+            log_index: None,
+        }
+    }
+
+    pub fn at_log(block: u64, hash: Option<B256>, log_index: Option<u64>) -> Self {
+        Self {
+            block,
+            hash,
+            log_index,
+        }
+    }
+
+    fn position(&self) -> (u64, u64) {
+        (self.block, self.log_index.unwrap_or(u64::MAX))
+    }
+
+    /// Whether a log at `block`/`log_index` still has to be applied to a state synced
+    /// at this point
+    pub fn precedes_log(&self, block: u64, log_index: Option<u64>) -> bool {
+        match log_index {
+            Some(index) => (block, index) > self.position(),
+            None => block > self.block,
+        }
+    }
+
+    /// Whether a state read at this point is at least as recent as one synced at
+    /// `synced`, states with no known point are never replaced
+    pub fn supersedes(&self, synced: Option<&SyncPoint>) -> bool {
+        synced.is_some_and(|s| s.position() <= self.position())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sync_point_ordering() {
+        let read = SyncPoint::at_block(100, B256::ZERO);
+        assert!(!read.precedes_log(100, Some(7)));
+        assert!(!read.precedes_log(99, Some(0)));
+        assert!(read.precedes_log(101, Some(0)));
+
+        let applied = SyncPoint::at_log(100, None, Some(3));
+        assert!(applied.precedes_log(100, Some(4)));
+        assert!(!applied.precedes_log(100, Some(3)));
+
+        assert!(read.supersedes(Some(&applied)));
+        assert!(!applied.supersedes(Some(&read)));
+        assert!(!read.supersedes(None));
+    }
+}
@@ -16,4 +16,134 @@ pub struct TicksBitMap {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TickData {
     pub liquidity_net: Option<i128>,
+    pub liquidity_gross: Option<u128>,
+}
+
+impl PoolWords {
+    /// Applies a position change of `liquidity_delta` between `tick_lower` and
+    /// `tick_upper`, the same way the pool contract updates its ticks.
+    ///
+    /// Ticks on words that are not loaded are left alone, loading the word later
+    /// brings them in already updated.
+    pub fn update_position(
+        &mut self,
+        tick_lower: I24,
+        tick_upper: I24,
+        tick_spacing: I24,
+        liquidity_delta: i128,
+    ) {
+        self.update_tick(tick_lower, tick_spacing, liquidity_delta, liquidity_delta);
+        self.update_tick(tick_upper, tick_spacing, liquidity_delta, -liquidity_delta);
+    }
+
+    fn update_tick(&mut self, tick: I24, tick_spacing: I24, gross_delta: i128, net_delta: i128) {
+        let compressed = tick.as_i32().div_euclid(tick_spacing.as_i32());
+        let (pos, bit) = ((compressed >> 8) as i16, (compressed & 0xff) as usize);
+        let Some(word) = self.words.get_mut(&pos) else {
+            return;
+        };
+
+        // a tick missing from a loaded word was not initialized
+        let data = word.ticks.entry(tick).or_insert(TickData {
+            liquidity_net: Some(0),
+            liquidity_gross: Some(0),
+        });
+        data.liquidity_net = data.liquidity_net.map(|n| n.saturating_add(net_delta));
+        data.liquidity_gross = data
+            .liquidity_gross
+            .map(|g| g.saturating_add_signed(gross_delta));
+
+        let initialized = match (data.liquidity_gross, data.liquidity_net) {
+            (Some(gross), _) => gross != 0,
+            (None, Some(net)) => net != 0,
+            (None, None) => true,
+        };
+        word.bitmap.set_bit(bit, initialized);
+        if !initialized {
+            word.ticks.remove(&tick);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tick(tick: i32) -> I24 {
+        I24::try_from(tick).unwrap()
+    }
+
+    fn loaded(positions: &[i16]) -> PoolWords {
+        let words = positions
+            .iter()
+            .map(|pos| {
+                let word = TicksBitMap {
+                    bitmap: U256::ZERO,
+                    ticks: BTreeMap::new(),
+                };
+                (*pos, word)
+            })
+            .collect();
+        PoolWords { words }
+    }
+
+    #[test]
+    fn mint_then_burn_back_to_zero() {
+        let spacing = tick(60);
+        let mut words = loaded(&[0]);
+
+        words.update_position(tick(60), tick(120), spacing, 1000);
+        let word = &words.words[&0];
+        assert_eq!(word.bitmap, (U256::ONE << 1) | (U256::ONE << 2));
+        assert_eq!(word.ticks[&tick(60)].liquidity_net, Some(1000));
+        assert_eq!(word.ticks[&tick(120)].liquidity_net, Some(-1000));
+        assert_eq!(word.ticks[&tick(120)].liquidity_gross, Some(1000));
+
+        // a second position sharing a tick keeps it initialized
+        words.update_position(tick(120), tick(180), spacing, 500);
+        words.update_position(tick(60), tick(120), spacing, -1000);
+        let word = &words.words[&0];
+        assert_eq!(word.bitmap, (U256::ONE << 2) | (U256::ONE << 3));
+        assert!(!word.ticks.contains_key(&tick(60)));
+        assert_eq!(word.ticks[&tick(120)].liquidity_net, Some(500));
+        assert_eq!(word.ticks[&tick(120)].liquidity_gross, Some(500));
+
+        words.update_position(tick(120), tick(180), spacing, -500);
+        let word = &words.words[&0];
+        assert_eq!(word.bitmap, U256::ZERO);
+        assert!(word.ticks.is_empty());
+    }
+
+    #[test]
+    fn ticks_of_words_not_loaded_are_left_alone() {
+        let spacing = tick(60);
+        let mut words = loaded(&[0]);
+
+        // the upper tick compresses to 512, on word 2
+        words.update_position(tick(60), tick(60 * 512), spacing, 1000);
+        assert_eq!(words.words.len(), 1);
+        let word = &words.words[&0];
+        assert_eq!(word.bitmap, U256::ONE << 1);
+        assert_eq!(word.ticks.len(), 1);
+        assert_eq!(word.ticks[&tick(60)].liquidity_net, Some(1000));
+    }
+
+    #[test]
+    fn negative_ticks_round_down_to_their_word() {
+        let spacing = tick(60);
+        let mut words = loaded(&[-1, 0]);
+
+        // -60 compresses to -1, the last bit of word -1, and -15360 to -256, its first
+        words.update_position(tick(-15360), tick(-60), spacing, 1000);
+        let word = &words.words[&-1];
+        assert_eq!(word.bitmap, (U256::ONE << 255) | U256::ONE);
+        assert_eq!(word.ticks[&tick(-15360)].liquidity_net, Some(1000));
+        assert_eq!(word.ticks[&tick(-60)].liquidity_net, Some(-1000));
+        assert_eq!(words.words[&0].bitmap, U256::ZERO);
+
+        // a tick that isn't a multiple of the spacing still rounds towards -inf
+        words.update_position(tick(-61), tick(0), spacing, 1);
+        assert!(words.words[&-1].bitmap.bit(254));
+        assert!(words.words[&0].bitmap.bit(0));
+    }
 }
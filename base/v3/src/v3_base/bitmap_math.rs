@@ -1,5 +1,7 @@
 use alloy_primitives::{U256, aliases::I24};
 
+use crate::v3_base::tick_math::{MAX_TICK, MIN_TICK};
+
 /// Normalize a tick by tick spacing (division towards zero)
 pub fn normalize_tick(current_tick: I24, tick_spacing: I24) -> I24 {
     current_tick.div_euclid(tick_spacing)
@@ -31,6 +33,16 @@ pub fn get_pos_from_tick(tick: I24, tick_spacing: I24) -> i16 {
     word_index(normalized_tick)
 }
 
+/// Lowest and highest word a pool with `tick_spacing` can have ticks in
+pub fn word_bounds(tick_spacing: I24) -> (i16, i16) {
+    let bound = |tick: i32| {
+        I24::try_from(tick)
+            .map(|t| get_pos_from_tick(t, tick_spacing))
+            .unwrap_or(0)
+    };
+    (bound(MIN_TICK), bound(MAX_TICK))
+}
+
 pub fn next_left(word: &U256, start: &i16) -> Option<usize> {
     // clamp start to valid range 0..=255
     let mut idx = *start.max(&0_i16).min(&255_i16) as usize;
@@ -1,25 +1,114 @@
-use crate::v3_base::states::TradeState;
+use std::fmt;
+
+use alloy_primitives::{
+    U256,
+    aliases::{I24, U24},
+};
+
 #[derive(Debug, Clone)]
 pub enum WordError {
     NotTried,
 }
 
-#[derive(Debug)]
+/// The loaded ticks can't take the trade any further
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TickError {
-    Overflow(TradeState),
-    Underflow(TradeState),
-    Unavailable(TradeState),
+    /// no loaded tick above `tick`
+    Overflow { tick: I24 },
+    /// no loaded tick at or below `tick`
+    Underflow { tick: I24 },
+    /// the next tick is known to be initialized but its liquidity wasn't read
+    Unavailable { tick: I24 },
 }
 
-#[derive(Debug)]
+/// Why a price, amount or liquidity computation failed, with the inputs it failed on.
+/// Each variant stands for a revert of the pool contract or of the libraries it uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MathError {
-    A(TradeState),
+    DivisionByZero {
+        numerator: U256,
+    },
+    /// `a * b / denominator` doesn't fit 256 bits
+    MulDivOverflow {
+        a: U256,
+        b: U256,
+        denominator: U256,
+    },
+    AddOverflow {
+        a: U256,
+        b: U256,
+    },
+    SubUnderflow {
+        a: U256,
+        b: U256,
+    },
+    /// a sqrt price outside of the 160 bits or the tick range prices can take
+    PriceOutOfBounds {
+        sqrt_price: U256,
+    },
+    TickOutOfRange {
+        tick: I24,
+    },
+    /// the limit is on the wrong side of the current price or past the price bounds
+    InvalidPriceLimit {
+        sqrt_price: U256,
+        sqrt_price_limit: U256,
+    },
+    /// prices and liquidity have to be positive to move the price by an amount
+    ZeroPriceOrLiquidity {
+        sqrt_price: U256,
+        liquidity: u128,
+    },
+    /// asked for more output than the virtual reserves of the range hold
+    InsufficientReserves {
+        sqrt_price: U256,
+        liquidity: u128,
+        amount_out: U256,
+    },
+    LiquidityUnderflow {
+        liquidity: U256,
+        delta: i128,
+    },
+    LiquidityOverflow {
+        liquidity: U256,
+        delta: i128,
+    },
+    /// liquidity over the 128 bits the pool keeps it in
+    LiquidityTooLarge {
+        liquidity: U256,
+    },
+    /// amount over the 255 bits of a signed swap amount
+    AmountTooLarge {
+        amount: U256,
+    },
+    /// fee at or above 100%
+    FeeOutOfRange {
+        fee: U24,
+    },
 }
 
-#[derive(Debug)]
+/// Part of the trade loop a math error happened in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TradeStage {
+    /// validating the trade before the first step
+    Start,
+    /// finding the next tick and its price
+    StepStart,
+    /// swapping within the range up to the next tick
+    SwapStep,
+    /// crossing the next tick or moving the current one
+    CrossTick,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TradeError {
     Tick(TickError),
-    Math(MathError),
+    /// `tick` is the current tick of the trade when `error` happened
+    Math {
+        stage: TradeStage,
+        tick: I24,
+        error: MathError,
+    },
     V2,
 }
 
@@ -29,8 +118,107 @@ impl From<TickError> for TradeError {
     }
 }
 
-impl From<MathError> for TradeError {
-    fn from(value: MathError) -> Self {
-        TradeError::Math(value)
+impl fmt::Display for TickError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TickError::Overflow { tick } => write!(f, "no loaded tick above {}", tick),
+            TickError::Underflow { tick } => write!(f, "no loaded tick at or below {}", tick),
+            TickError::Unavailable { tick } => write!(f, "liquidity of tick {} not loaded", tick),
+        }
+    }
+}
+
+impl fmt::Display for MathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MathError::DivisionByZero { numerator } => {
+                write!(f, "division of {} by zero", numerator)
+            }
+            MathError::MulDivOverflow { a, b, denominator } => {
+                write!(f, "{} * {} / {} overflows 256 bits", a, b, denominator)
+            }
+            MathError::AddOverflow { a, b } => write!(f, "{} + {} overflows 256 bits", a, b),
+            MathError::SubUnderflow { a, b } => write!(f, "{} - {} underflows", a, b),
+            MathError::PriceOutOfBounds { sqrt_price } => {
+                write!(f, "sqrt price {} out of bounds", sqrt_price)
+            }
+            MathError::TickOutOfRange { tick } => write!(f, "tick {} out of range", tick),
+            MathError::InvalidPriceLimit {
+                sqrt_price,
+                sqrt_price_limit,
+            } => write!(
+                f,
+                "sqrt price limit {} invalid for sqrt price {}",
+                sqrt_price_limit, sqrt_price
+            ),
+            MathError::ZeroPriceOrLiquidity {
+                sqrt_price,
+                liquidity,
+            } => write!(
+                f,
+                "sqrt price {} and liquidity {} must be positive",
+                sqrt_price, liquidity
+            ),
+            MathError::InsufficientReserves {
+                sqrt_price,
+                liquidity,
+                amount_out,
+            } => write!(
+                f,
+                "{} out is over the reserves of liquidity {} at sqrt price {}",
+                amount_out, liquidity, sqrt_price
+            ),
+            MathError::LiquidityUnderflow { liquidity, delta } => {
+                write!(f, "liquidity {} {:+} underflows", liquidity, delta)
+            }
+            MathError::LiquidityOverflow { liquidity, delta } => {
+                write!(f, "liquidity {} {:+} overflows", liquidity, delta)
+            }
+            MathError::LiquidityTooLarge { liquidity } => {
+                write!(f, "liquidity {} over 128 bits", liquidity)
+            }
+            MathError::AmountTooLarge { amount } => {
+                write!(f, "amount {} over the signed 256 bit range", amount)
+            }
+            MathError::FeeOutOfRange { fee } => write!(f, "fee {} at or over 100%", fee),
+        }
+    }
+}
+
+impl fmt::Display for TradeStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stage = match self {
+            TradeStage::Start => "trade start",
+            TradeStage::StepStart => "step start",
+            TradeStage::SwapStep => "swap step",
+            TradeStage::CrossTick => "tick cross",
+        };
+        f.write_str(stage)
+    }
+}
+
+impl fmt::Display for TradeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TradeError::Tick(err) => write!(f, "trade ran out of ticks: {}", err),
+            TradeError::Math { stage, tick, error } => {
+                write!(f, "{} failed at tick {}: {}", stage, tick, error)
+            }
+            TradeError::V2 => f.write_str("v2 trade failed"),
+        }
+    }
+}
+
+impl std::error::Error for TickError {}
+
+impl std::error::Error for MathError {}
+
+impl std::error::Error for TradeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TradeError::Tick(err) => Some(err),
+            TradeError::Math { error, .. } => Some(error),
+            TradeError::V2 => None,
+        }
     }
 }
@@ -0,0 +1,40 @@
+use alloy_primitives::{U256, U512, ruint::UintTryFrom};
+
+use crate::v3_base::err::MathError;
+
+/// Port of `FullMath.mulDiv`: `a * b / denominator` rounded down with a 512 bit
+/// intermediate product
+pub fn mul_div(a: U256, b: U256, denominator: U256) -> Result<U256, MathError> {
+    if denominator.is_zero() {
+        return Err(MathError::DivisionByZero {
+            numerator: a.saturating_mul(b),
+        });
+    }
+    let quotient = (U512::from(a) * U512::from(b)) / U512::from(denominator);
+    U256::uint_try_from(quotient).map_err(|_| MathError::MulDivOverflow { a, b, denominator })
+}
+
+/// Port of `FullMath.mulDivRoundingUp`
+pub fn mul_div_rounding_up(a: U256, b: U256, denominator: U256) -> Result<U256, MathError> {
+    let result = mul_div(a, b, denominator)?;
+    if a.mul_mod(b, denominator).is_zero() {
+        Ok(result)
+    } else {
+        result
+            .checked_add(U256::ONE)
+            .ok_or(MathError::MulDivOverflow { a, b, denominator })
+    }
+}
+
+/// Port of `UnsafeMath.divRoundingUp`, with a zero divisor being an error instead of
+/// returning zero like the assembly does
+pub fn div_rounding_up(x: U256, y: U256) -> Result<U256, MathError> {
+    let quotient = x
+        .checked_div(y)
+        .ok_or(MathError::DivisionByZero { numerator: x })?;
+    if (x % y).is_zero() {
+        Ok(quotient)
+    } else {
+        Ok(quotient + U256::ONE)
+    }
+}
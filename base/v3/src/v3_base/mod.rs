@@ -1,7 +1,10 @@
 pub mod bitmap;
 pub mod bitmap_math;
 pub mod err;
+pub mod full_math;
+pub mod sqrt_price_math;
 pub mod states;
+pub mod swap_math;
 pub mod tick_math;
 pub mod ticks;
 pub mod trade_math;
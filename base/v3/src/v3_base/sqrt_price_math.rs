@@ -0,0 +1,359 @@
+//! Port of Uniswap v3 `SqrtPriceMath`, every function rounds the same way the
+//! Solidity library does so quotes match the amounts the pool contract settles.
+//! Prices are Q64.96 sqrt prices and errors stand for a revert.
+
+use alloy_primitives::{U160, U256};
+
+use crate::v3_base::{
+    err::MathError,
+    full_math::{div_rounding_up, mul_div, mul_div_rounding_up},
+};
+
+const RESOLUTION: usize = 96;
+const Q96: U256 = U256::from_limbs([0, 1 << 32, 0, 0]);
+
+fn max_u160() -> U256 {
+    U256::from(U160::MAX)
+}
+
+/// `SafeCast.toUint160`
+fn to_u160(value: U256) -> Result<U256, MathError> {
+    if value <= max_u160() {
+        Ok(value)
+    } else {
+        Err(MathError::PriceOutOfBounds { sqrt_price: value })
+    }
+}
+
+fn checked_add(a: U256, b: U256) -> Result<U256, MathError> {
+    a.checked_add(b).ok_or(MathError::AddOverflow { a, b })
+}
+
+/// Next price after adding (`add`) or removing `amount` of token0, rounded up so the
+/// price moves less than the exact amount would move it
+pub fn get_next_sqrt_price_from_amount0_rounding_up(
+    sqrt_price: U256,
+    liquidity: u128,
+    amount: U256,
+    add: bool,
+) -> Result<U256, MathError> {
+    // we short circuit amount == 0 because the result is otherwise not guaranteed to
+    // equal the input price
+    if amount.is_zero() {
+        return Ok(sqrt_price);
+    }
+    let numerator1 = U256::from(liquidity) << RESOLUTION;
+
+    if add {
+        let denominator = amount
+            .checked_mul(sqrt_price)
+            .and_then(|product| numerator1.checked_add(product));
+        if let Some(denominator) = denominator {
+            // always fits 160 bits
+            return mul_div_rounding_up(numerator1, sqrt_price, denominator);
+        }
+        let quotient = numerator1
+            .checked_div(sqrt_price)
+            .ok_or(MathError::DivisionByZero {
+                numerator: numerator1,
+            })?;
+        div_rounding_up(numerator1, checked_add(quotient, amount)?)
+    } else {
+        // the product has to fit and be below the numerator, otherwise the amount
+        // removed is more than the virtual reserves of token0
+        let insufficient = MathError::InsufficientReserves {
+            sqrt_price,
+            liquidity,
+            amount_out: amount,
+        };
+        let product = amount.checked_mul(sqrt_price).ok_or(insufficient)?;
+        if numerator1 <= product {
+            return Err(insufficient);
+        }
+        to_u160(mul_div_rounding_up(
+            numerator1,
+            sqrt_price,
+            numerator1 - product,
+        )?)
+    }
+}
+
+/// Next price after adding (`add`) or removing `amount` of token1, rounded down so the
+/// price moves less than the exact amount would move it
+pub fn get_next_sqrt_price_from_amount1_rounding_down(
+    sqrt_price: U256,
+    liquidity: u128,
+    amount: U256,
+    add: bool,
+) -> Result<U256, MathError> {
+    let liquidity_wide = U256::from(liquidity);
+
+    if add {
+        let quotient = if amount <= max_u160() {
+            let numerator = amount << RESOLUTION;
+            numerator
+                .checked_div(liquidity_wide)
+                .ok_or(MathError::DivisionByZero { numerator })?
+        } else {
+            mul_div(amount, Q96, liquidity_wide)?
+        };
+        to_u160(checked_add(sqrt_price, quotient)?)
+    } else {
+        let quotient = if amount <= max_u160() {
+            div_rounding_up(amount << RESOLUTION, liquidity_wide)?
+        } else {
+            mul_div_rounding_up(amount, Q96, liquidity_wide)?
+        };
+        if sqrt_price <= quotient {
+            return Err(MathError::InsufficientReserves {
+                sqrt_price,
+                liquidity,
+                amount_out: amount,
+            });
+        }
+        Ok(sqrt_price - quotient)
+    }
+}
+
+/// Next price after `amount_in` of token0 (`zero_for_one`) or token1 is swapped in,
+/// never past the price the exact amount would reach
+pub fn get_next_sqrt_price_from_input(
+    sqrt_price: U256,
+    liquidity: u128,
+    amount_in: U256,
+    zero_for_one: bool,
+) -> Result<U256, MathError> {
+    if sqrt_price.is_zero() || liquidity == 0 {
+        return Err(MathError::ZeroPriceOrLiquidity {
+            sqrt_price,
+            liquidity,
+        });
+    }
+
+    if zero_for_one {
+        get_next_sqrt_price_from_amount0_rounding_up(sqrt_price, liquidity, amount_in, true)
+    } else {
+        get_next_sqrt_price_from_amount1_rounding_down(sqrt_price, liquidity, amount_in, true)
+    }
+}
+
+/// Next price after `amount_out` of token1 (`zero_for_one`) or token0 is swapped out,
+/// always at or past the price the exact amount would reach
+pub fn get_next_sqrt_price_from_output(
+    sqrt_price: U256,
+    liquidity: u128,
+    amount_out: U256,
+    zero_for_one: bool,
+) -> Result<U256, MathError> {
+    if sqrt_price.is_zero() || liquidity == 0 {
+        return Err(MathError::ZeroPriceOrLiquidity {
+            sqrt_price,
+            liquidity,
+        });
+    }
+
+    if zero_for_one {
+        get_next_sqrt_price_from_amount1_rounding_down(sqrt_price, liquidity, amount_out, false)
+    } else {
+        get_next_sqrt_price_from_amount0_rounding_up(sqrt_price, liquidity, amount_out, false)
+    }
+}
+
+/// Amount of token0 between two prices, `liquidity / sqrt(lower) - liquidity / sqrt(upper)`
+pub fn get_amount0_delta(
+    sqrt_price_a: U256,
+    sqrt_price_b: U256,
+    liquidity: u128,
+    round_up: bool,
+) -> Result<U256, MathError> {
+    let (lower, upper) = if sqrt_price_a > sqrt_price_b {
+        (sqrt_price_b, sqrt_price_a)
+    } else {
+        (sqrt_price_a, sqrt_price_b)
+    };
+    if lower.is_zero() {
+        return Err(MathError::PriceOutOfBounds { sqrt_price: lower });
+    }
+
+    let numerator1 = U256::from(liquidity) << RESOLUTION;
+    let numerator2 = upper - lower;
+
+    if round_up {
+        div_rounding_up(mul_div_rounding_up(numerator1, numerator2, upper)?, lower)
+    } else {
+        Ok(mul_div(numerator1, numerator2, upper)? / lower)
+    }
+}
+
+/// Amount of token1 between two prices, `liquidity * (sqrt(upper) - sqrt(lower))`
+pub fn get_amount1_delta(
+    sqrt_price_a: U256,
+    sqrt_price_b: U256,
+    liquidity: u128,
+    round_up: bool,
+) -> Result<U256, MathError> {
+    let (lower, upper) = if sqrt_price_a > sqrt_price_b {
+        (sqrt_price_b, sqrt_price_a)
+    } else {
+        (sqrt_price_a, sqrt_price_b)
+    };
+
+    if round_up {
+        mul_div_rounding_up(U256::from(liquidity), upper - lower, Q96)
+    } else {
+        mul_div(U256::from(liquidity), upper - lower, Q96)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // encodePriceSqrt(1, 1) of the Uniswap test utilities
+    const PRICE_1_1: U256 = Q96;
+
+    fn n(value: &str) -> U256 {
+        U256::from_str_radix(value, 10).unwrap()
+    }
+
+    fn e18(value: u64) -> U256 {
+        U256::from(value) * U256::from(10).pow(U256::from(18))
+    }
+
+    #[test]
+    fn next_price_from_input() {
+        let liquidity = 10u128.pow(18);
+        assert_eq!(
+            get_next_sqrt_price_from_input(PRICE_1_1, liquidity / 10, U256::ZERO, true),
+            Ok(PRICE_1_1)
+        );
+        assert_eq!(
+            get_next_sqrt_price_from_input(PRICE_1_1, liquidity, e18(1) / U256::from(10), false),
+            Ok(n("87150978765690771352898345369"))
+        );
+        assert_eq!(
+            get_next_sqrt_price_from_input(PRICE_1_1, liquidity, e18(1) / U256::from(10), true),
+            Ok(n("72025602285694852357767227579"))
+        );
+        assert_eq!(
+            get_next_sqrt_price_from_input(PRICE_1_1, liquidity * 10, U256::ONE << 100, true),
+            Ok(n("624999999995069620"))
+        );
+        assert_eq!(
+            get_next_sqrt_price_from_input(PRICE_1_1, 1, U256::MAX / U256::from(2), true),
+            Ok(U256::ONE)
+        );
+
+        // the minimum price for max inputs
+        let sqrt_price = max_u160();
+        let liquidity = u128::MAX;
+        let max_amount = U256::MAX - (U256::from(liquidity) << 96) / sqrt_price;
+        assert_eq!(
+            get_next_sqrt_price_from_input(sqrt_price, liquidity, max_amount, true),
+            Ok(U256::ONE)
+        );
+
+        assert_eq!(
+            get_next_sqrt_price_from_input(U256::ZERO, 1, U256::ONE, true),
+            Err(MathError::ZeroPriceOrLiquidity {
+                sqrt_price: U256::ZERO,
+                liquidity: 1
+            })
+        );
+        assert_eq!(
+            get_next_sqrt_price_from_input(U256::ONE, 0, U256::ONE, true),
+            Err(MathError::ZeroPriceOrLiquidity {
+                sqrt_price: U256::ONE,
+                liquidity: 0
+            })
+        );
+    }
+
+    #[test]
+    fn next_price_from_output() {
+        let liquidity = 10u128.pow(18);
+        assert_eq!(
+            get_next_sqrt_price_from_output(PRICE_1_1, liquidity, e18(1) / U256::from(10), false),
+            Ok(n("88031291682515930659493278152"))
+        );
+        assert_eq!(
+            get_next_sqrt_price_from_output(PRICE_1_1, liquidity, e18(1) / U256::from(10), true),
+            Ok(n("71305346262837903834189555302"))
+        );
+
+        // output amount at or over the virtual reserves of the pool
+        let sqrt_price = n("20282409603651670423947251286016");
+        assert_eq!(
+            get_next_sqrt_price_from_output(sqrt_price, 1024, U256::from(4), false),
+            Err(MathError::InsufficientReserves {
+                sqrt_price,
+                liquidity: 1024,
+                amount_out: U256::from(4)
+            })
+        );
+        assert_eq!(
+            get_next_sqrt_price_from_output(sqrt_price, 1024, U256::from(262144), true),
+            Err(MathError::InsufficientReserves {
+                sqrt_price,
+                liquidity: 1024,
+                amount_out: U256::from(262144)
+            })
+        );
+        assert_eq!(
+            get_next_sqrt_price_from_output(sqrt_price, 1024, U256::from(262143), true),
+            Ok(n("77371252455336267181195264"))
+        );
+    }
+
+    #[test]
+    fn amount_deltas() {
+        // encodePriceSqrt(121, 100)
+        let price_121_100 = n("87150978765690771352898345369");
+        let liquidity = 10u128.pow(18);
+
+        assert_eq!(
+            get_amount0_delta(PRICE_1_1, price_121_100, 0, true),
+            Ok(U256::ZERO)
+        );
+        assert_eq!(
+            get_amount0_delta(PRICE_1_1, PRICE_1_1, liquidity, true),
+            Ok(U256::ZERO)
+        );
+
+        let amount0 = get_amount0_delta(PRICE_1_1, price_121_100, liquidity, true).unwrap();
+        assert_eq!(amount0, n("90909090909090910"));
+        assert_eq!(
+            get_amount0_delta(PRICE_1_1, price_121_100, liquidity, false),
+            Ok(amount0 - U256::ONE)
+        );
+
+        let amount1 = get_amount1_delta(PRICE_1_1, price_121_100, liquidity, true).unwrap();
+        assert_eq!(amount1, n("100000000000000000"));
+        assert_eq!(
+            get_amount1_delta(PRICE_1_1, price_121_100, liquidity, false),
+            Ok(amount1 - U256::ONE)
+        );
+
+        // prices whose intermediate product overflows 256 bits
+        let (low, high) = (U256::from(1) << 141, U256::from(1) << 144);
+        let up = get_amount0_delta(low, high, liquidity, true).unwrap();
+        let down = get_amount0_delta(low, high, liquidity, false).unwrap();
+        assert_eq!(up, down + U256::ONE);
+    }
+
+    #[test]
+    fn swap_computation() {
+        let sqrt_price = n("1025574284609383690408304870162715216695788925244");
+        let liquidity = 50015962439936049619261659728067971248u128;
+        let sqrt_q =
+            get_next_sqrt_price_from_input(sqrt_price, liquidity, U256::from(406), true).unwrap();
+        assert_eq!(
+            sqrt_q,
+            n("1025574284609383582644711336373707553698163132913")
+        );
+        assert_eq!(
+            get_amount0_delta(sqrt_q, sqrt_price, liquidity, true),
+            Ok(U256::from(406))
+        );
+    }
+}
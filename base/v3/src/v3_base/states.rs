@@ -15,25 +15,66 @@ pub struct TradeReceipt {
     pub from0: bool,
     pub amount_in: U256,
     pub amount_out: U256,
+    /// specified amount left when the trade stopped at its price limit
+    pub remaining: U256,
 }
 
 #[derive(Debug, Clone, Copy)]
 pub struct TradeState {
+    pub fee: U24,
     pub fee_amount: U256,
+    /// input swapped so far, fee included
     pub amount_in: U256,
     pub amount_out: U256,
     pub liquidity: U256,
     pub x96price: U256,
     pub tick: I24,
+    /// specified amount not swapped yet, input for exact input trades and output for
+    /// exact output ones
     pub remaining: U256,
     pub from0: bool,
+    pub exact_in: bool,
+    /// sqrt price the trade stops at, with `remaining` left unswapped
+    pub price_limit: U256,
     pub step: TradeStep,
 }
+
+impl TradeState {
+    pub fn receipt(&self, pool: Address, token0: Address, token1: Address) -> TradeReceipt {
+        TradeReceipt {
+            fee: self.fee,
+            fee_amount: self.fee_amount,
+            token0,
+            token1,
+            pool,
+            from0: self.from0,
+            amount_in: self.amount_in,
+            amount_out: self.amount_out,
+            remaining: self.remaining,
+        }
+    }
+}
+/// How a simulated trade ended
+#[derive(Debug, Clone)]
+pub enum TradeOutcome {
+    /// the specified amount was swapped or the price reached the limit of the trade
+    Filled(TradeState),
+    /// the loaded ticks ran out, `state` is the trade up to there and can be resumed
+    /// with `trade_math::retry` once `missing_words` are loaded into the pool. No
+    /// missing words means the trade reached the end of the tick range
+    Partial {
+        state: TradeState,
+        missing_words: Vec<i16>,
+    },
+}
+
 #[derive(Debug, Clone, Copy, Default)]
 pub struct TradeStep {
-    pub amount_possible: U256,
+    pub price_start: U256,
     pub next_tick: Tick,
     pub next_tick_index: usize,
     pub next_price: U256,
-    pub delta: U256,
+    pub amount_in: U256,
+    pub amount_out: U256,
+    pub fee_amount: U256,
 }
@@ -0,0 +1,276 @@
+//! Port of Uniswap v3 `SwapMath`, a single step of a swap within one tick range
+
+use alloy_primitives::{I256, U256, aliases::U24};
+
+use crate::v3_base::{
+    err::MathError,
+    full_math::{mul_div, mul_div_rounding_up},
+    sqrt_price_math::{
+        get_amount0_delta, get_amount1_delta, get_next_sqrt_price_from_input,
+        get_next_sqrt_price_from_output,
+    },
+};
+
+const FEE_DENOMINATOR: u32 = 1_000_000;
+
+/// Outcome of `compute_swap_step`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SwapStepResult {
+    pub sqrt_price_next: U256,
+    pub amount_in: U256,
+    pub amount_out: U256,
+    pub fee_amount: U256,
+}
+
+/// Swaps within the range from `sqrt_price_current` to `sqrt_price_target`, the
+/// direction follows from the two prices. A positive `amount_remaining` is an exact
+/// input still to be spent, fee included, a negative one an exact output still to be
+/// received. `fee_pips` is in hundredths of a bip.
+///
+/// Errors where the contract would revert
+pub fn compute_swap_step(
+    sqrt_price_current: U256,
+    sqrt_price_target: U256,
+    liquidity: u128,
+    amount_remaining: I256,
+    fee_pips: U24,
+) -> Result<SwapStepResult, MathError> {
+    let zero_for_one = sqrt_price_current >= sqrt_price_target;
+    let exact_in = !amount_remaining.is_negative();
+    let remaining = amount_remaining.unsigned_abs();
+    let fee_complement = U256::from(FEE_DENOMINATOR)
+        .checked_sub(U256::from(fee_pips))
+        .filter(|complement| !complement.is_zero())
+        .ok_or(MathError::FeeOutOfRange { fee: fee_pips })?;
+    let fee_pips = U256::from(fee_pips);
+
+    let mut amount_in = U256::ZERO;
+    let mut amount_out = U256::ZERO;
+
+    let sqrt_price_next = if exact_in {
+        let remaining_less_fee = mul_div(remaining, fee_complement, U256::from(FEE_DENOMINATOR))?;
+        amount_in = if zero_for_one {
+            get_amount0_delta(sqrt_price_target, sqrt_price_current, liquidity, true)?
+        } else {
+            get_amount1_delta(sqrt_price_current, sqrt_price_target, liquidity, true)?
+        };
+        if remaining_less_fee >= amount_in {
+            sqrt_price_target
+        } else {
+            get_next_sqrt_price_from_input(
+                sqrt_price_current,
+                liquidity,
+                remaining_less_fee,
+                zero_for_one,
+            )?
+        }
+    } else {
+        amount_out = if zero_for_one {
+            get_amount1_delta(sqrt_price_target, sqrt_price_current, liquidity, false)?
+        } else {
+            get_amount0_delta(sqrt_price_current, sqrt_price_target, liquidity, false)?
+        };
+        if remaining >= amount_out {
+            sqrt_price_target
+        } else {
+            get_next_sqrt_price_from_output(sqrt_price_current, liquidity, remaining, zero_for_one)?
+        }
+    };
+
+    // the amounts computed against the target only hold when the target was reached
+    let max = sqrt_price_target == sqrt_price_next;
+    if zero_for_one {
+        if !(max && exact_in) {
+            amount_in = get_amount0_delta(sqrt_price_next, sqrt_price_current, liquidity, true)?;
+        }
+        if !max || exact_in {
+            amount_out = get_amount1_delta(sqrt_price_next, sqrt_price_current, liquidity, false)?;
+        }
+    } else {
+        if !(max && exact_in) {
+            amount_in = get_amount1_delta(sqrt_price_current, sqrt_price_next, liquidity, true)?;
+        }
+        if !max || exact_in {
+            amount_out = get_amount0_delta(sqrt_price_current, sqrt_price_next, liquidity, false)?;
+        }
+    }
+
+    // the output can't be more than what was asked for
+    if !exact_in && amount_out > remaining {
+        amount_out = remaining;
+    }
+
+    let fee_amount = if exact_in && sqrt_price_next != sqrt_price_target {
+        // the whole remaining input was used, what isn't swapped is the fee
+        remaining
+            .checked_sub(amount_in)
+            .ok_or(MathError::SubUnderflow {
+                a: remaining,
+                b: amount_in,
+            })?
+    } else {
+        mul_div_rounding_up(amount_in, fee_pips, fee_complement)?
+    };
+
+    Ok(SwapStepResult {
+        sqrt_price_next,
+        amount_in,
+        amount_out,
+        fee_amount,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v3_base::sqrt_price_math::get_next_sqrt_price_from_input;
+
+    fn n(value: &str) -> U256 {
+        U256::from_str_radix(value, 10).unwrap()
+    }
+
+    fn e18(value: u64) -> U256 {
+        U256::from(value) * U256::from(10).pow(U256::from(18))
+    }
+
+    fn signed(value: U256) -> I256 {
+        I256::from_raw(value)
+    }
+
+    fn fee(pips: u32) -> U24 {
+        U24::from(pips)
+    }
+
+    // encodePriceSqrt values of the Uniswap test utilities
+    const PRICE_1_1: &str = "79228162514264337593543950336";
+    const PRICE_101_100: &str = "79623317895830914510639640423";
+    const PRICE_1000_100: &str = "250541448375047931186413801569";
+    const PRICE_10000_100: &str = "792281625142643375935439503360";
+
+    #[test]
+    fn exact_in_capped_at_price_target_one_for_zero() {
+        let (price, target) = (n(PRICE_1_1), n(PRICE_101_100));
+        let liquidity = 2 * 10u128.pow(18);
+        let step = compute_swap_step(price, target, liquidity, signed(e18(1)), fee(600)).unwrap();
+
+        assert_eq!(step.amount_in, n("9975124224178055"));
+        assert_eq!(step.fee_amount, n("5988667735148"));
+        assert_eq!(step.amount_out, n("9925619580021728"));
+        assert!(step.amount_in + step.fee_amount < e18(1));
+        assert_eq!(step.sqrt_price_next, target);
+
+        let whole_input = get_next_sqrt_price_from_input(price, liquidity, e18(1), false).unwrap();
+        assert!(step.sqrt_price_next < whole_input);
+    }
+
+    #[test]
+    fn exact_out_capped_at_price_target_one_for_zero() {
+        let (price, target) = (n(PRICE_1_1), n(PRICE_101_100));
+        let liquidity = 2 * 10u128.pow(18);
+        let step = compute_swap_step(price, target, liquidity, -signed(e18(1)), fee(600)).unwrap();
+
+        assert_eq!(step.amount_in, n("9975124224178055"));
+        assert_eq!(step.fee_amount, n("5988667735148"));
+        assert_eq!(step.amount_out, n("9925619580021728"));
+        assert!(step.amount_out < e18(1));
+        assert_eq!(step.sqrt_price_next, target);
+    }
+
+    #[test]
+    fn exact_in_fully_spent_one_for_zero() {
+        let (price, target) = (n(PRICE_1_1), n(PRICE_1000_100));
+        let liquidity = 2 * 10u128.pow(18);
+        let step = compute_swap_step(price, target, liquidity, signed(e18(1)), fee(600)).unwrap();
+
+        assert_eq!(step.amount_in, n("999400000000000000"));
+        assert_eq!(step.fee_amount, n("600000000000000"));
+        assert_eq!(step.amount_out, n("666399946655997866"));
+        assert_eq!(step.amount_in + step.fee_amount, e18(1));
+        assert!(step.sqrt_price_next < target);
+
+        let less_fee = e18(1) - step.fee_amount;
+        let whole_input = get_next_sqrt_price_from_input(price, liquidity, less_fee, false);
+        assert_eq!(Ok(step.sqrt_price_next), whole_input);
+    }
+
+    #[test]
+    fn exact_out_fully_received_one_for_zero() {
+        let (price, target) = (n(PRICE_1_1), n(PRICE_10000_100));
+        let liquidity = 2 * 10u128.pow(18);
+        let step = compute_swap_step(price, target, liquidity, -signed(e18(1)), fee(600)).unwrap();
+
+        assert_eq!(step.amount_in, n("2000000000000000000"));
+        assert_eq!(step.fee_amount, n("1200720432259356"));
+        assert_eq!(step.amount_out, e18(1));
+        assert!(step.sqrt_price_next < target);
+    }
+
+    #[test]
+    fn amount_out_capped_at_desired_amount() {
+        let step = compute_swap_step(
+            n("417332158212080721273783715441582"),
+            n("1452870262520218020823638996"),
+            159344665391607089467575320103,
+            -signed(U256::ONE),
+            fee(1),
+        )
+        .unwrap();
+
+        assert_eq!(step.amount_in, U256::ONE);
+        assert_eq!(step.fee_amount, U256::ONE);
+        assert_eq!(step.amount_out, U256::ONE);
+        assert_eq!(step.sqrt_price_next, n("417332158212080721273783715441581"));
+    }
+
+    #[test]
+    fn target_price_of_1_uses_partial_input() {
+        let amount = n("3915081100057732413702495386755767");
+        let step = compute_swap_step(U256::from(2), U256::ONE, 1, signed(amount), fee(1)).unwrap();
+
+        assert_eq!(step.amount_in, n("39614081257132168796771975168"));
+        assert_eq!(step.fee_amount, n("39614120871253040049813"));
+        assert!(step.amount_in + step.fee_amount <= amount);
+        assert_eq!(step.amount_out, U256::ZERO);
+        assert_eq!(step.sqrt_price_next, U256::ONE);
+    }
+
+    #[test]
+    fn entire_input_taken_as_fee() {
+        let step = compute_swap_step(
+            U256::from(2413),
+            n("79887613182836312"),
+            1985041575832132834610021537970,
+            signed(U256::from(10)),
+            fee(1872),
+        )
+        .unwrap();
+
+        assert_eq!(step.amount_in, U256::ZERO);
+        assert_eq!(step.fee_amount, U256::from(10));
+        assert_eq!(step.amount_out, U256::ZERO);
+        assert_eq!(step.sqrt_price_next, U256::from(2413));
+    }
+
+    #[test]
+    fn insufficient_liquidity_exact_output() {
+        let price = n("20282409603651670423947251286016");
+
+        // price moving up
+        let target = price * U256::from(11) / U256::from(10);
+        let step =
+            compute_swap_step(price, target, 1024, -signed(U256::from(4)), fee(3000)).unwrap();
+        assert_eq!(step.amount_out, U256::ZERO);
+        assert_eq!(step.sqrt_price_next, target);
+        assert_eq!(step.amount_in, U256::from(26215));
+        assert_eq!(step.fee_amount, U256::from(79));
+
+        // price moving down
+        let target = price * U256::from(9) / U256::from(10);
+        let step =
+            compute_swap_step(price, target, 1024, -signed(U256::from(263000)), fee(3000)).unwrap();
+        assert_eq!(step.amount_out, U256::from(26214));
+        assert_eq!(step.sqrt_price_next, target);
+        assert_eq!(step.amount_in, U256::ONE);
+        assert_eq!(step.fee_amount, U256::ONE);
+    }
+}
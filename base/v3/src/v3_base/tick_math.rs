@@ -1,8 +1,16 @@
 use alloy_primitives::{I256, U256, U512, aliases::I24};
 
+pub const MIN_TICK: i32 = -887272;
+pub const MAX_TICK: i32 = 887272;
+/// `price_from_tick(MIN_TICK)`, the lowest price a pool can reach
+pub const MIN_SQRT_RATIO: U256 = U256::from_limbs([4295128739, 0, 0, 0]);
+/// `price_from_tick(MAX_TICK)`, prices can only get below it
+pub const MAX_SQRT_RATIO: U256 =
+    U256::from_limbs([0x5d951d5263988d26, 0xefd1fc6a50648849, 0xfffd8963, 0]);
+
 pub fn price_from_tick(target_tick: I24) -> Option<U256> {
     println!("calculating price for tick: {}", target_tick);
-    let max_tick: I24 = I24::try_from(887272).unwrap();
+    let max_tick: I24 = I24::try_from(MAX_TICK).unwrap();
     let abs_tick = target_tick.abs();
 
     if abs_tick > max_tick {
@@ -134,12 +142,7 @@ pub fn price_from_tick(target_tick: I24) -> Option<U256> {
 // Convert a sqrt price Q128.96 to the nearest tick index (I24)
 /// Port of Uniswap V3's TickMath.getTickAtSqrtRatio
 pub fn tick_from_price(sqrt_price_x96: U256) -> Option<I24> {
-    // Define bounds as U256 to avoid u128 overflow
-    let min_sqrt = U256::from(4295128739u64);
-    let max_sqrt =
-        U256::from_str_radix("1461446703485210103287273052203988822378723970342", 10).unwrap();
-
-    if sqrt_price_x96 < min_sqrt || sqrt_price_x96 >= max_sqrt {
+    if sqrt_price_x96 < MIN_SQRT_RATIO || sqrt_price_x96 >= MAX_SQRT_RATIO {
         eprintln!("Sqrt price {} out of bounds", sqrt_price_x96);
         return None;
     }
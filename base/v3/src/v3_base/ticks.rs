@@ -47,10 +47,10 @@ impl Ticks {
             let stick = self.ticks[self_idx].tick;
             let ntick = ticks[new_idx].tick;
 
-            if stick > ntick {
+            if stick < ntick {
                 all_ticks.push(self.ticks[self_idx]);
                 self_idx += 1;
-            } else if stick < ntick {
+            } else if stick > ntick {
                 all_ticks.push(ticks[new_idx]);
                 new_idx += 1;
             } else {
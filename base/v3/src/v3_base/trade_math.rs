@@ -1,30 +1,129 @@
 use crate::v3_base::{
-    states::{TradeState, TradeStep},
-    tick_math::{price_from_tick, tick_from_price},
+    bitmap_math::{get_pos_from_tick, normalize_tick, word_bounds, word_index},
+    states::{TradeOutcome, TradeState, TradeStep},
+    swap_math::compute_swap_step,
+    tick_math::{MAX_SQRT_RATIO, MIN_SQRT_RATIO, price_from_tick, tick_from_price},
     ticks::Ticks,
     v3_state::V3State,
-    x96price_math::{compute_price_from0, compute_price_from1},
+    x96price_math::update_liquidity,
+};
+use alloy_primitives::{
+    I256, U256,
+    aliases::{I24, U24},
 };
-use alloy_primitives::{U256, U512, aliases::U24};
 
-use super::err::{MathError, TickError, TradeError};
+use super::err::{MathError, TickError, TradeError, TradeStage};
 
-pub fn retry(trade_state: TradeState, ticks: &Ticks) -> Result<TradeState, TradeError> {
+/// Resumes a partial trade on `pool`, after the words it was missing were loaded
+pub fn retry(trade_state: TradeState, pool: &V3State) -> Result<TradeOutcome, TradeError> {
     println!("retrying trade");
-    trade_loop(trade_state, ticks)
+    outcome(trade_state, pool)
 }
 
+/// Swaps `amount_in`, selling token0 when `from0`, until it is spent or the price
+/// reaches `sqrt_price_limit`. Without a limit the trade can go up to the price bounds.
 pub fn trade(
     pool: &V3State,
     fee: &U24,
     amount_in: U256,
     from0: bool,
-) -> Result<TradeState, TradeError> {
-    let trade_state = trade_start(pool, fee, amount_in, from0)?;
-    trade_loop(trade_state, &pool.ticks)?;
+    sqrt_price_limit: Option<U256>,
+) -> Result<TradeOutcome, TradeError> {
+    let trade_state = trade_start(pool, fee, amount_in, from0, sqrt_price_limit)?;
+    outcome(trade_state, pool)
+}
 
-    // build Trade
-    Ok(trade_state)
+/// Input, fee included, needed to receive `amount_out`, selling token0 when `from0`
+pub fn trade_exact_out(
+    pool: &V3State,
+    fee: &U24,
+    amount_out: U256,
+    from0: bool,
+    sqrt_price_limit: Option<U256>,
+) -> Result<TradeOutcome, TradeError> {
+    let trade_state = trade_start_exact_out(pool, fee, amount_out, from0, sqrt_price_limit)?;
+    outcome(trade_state, pool)
+}
+
+/// Runs the trade on `pool`, running out of loaded ticks or reaching a word that isn't
+/// loaded is a partial outcome with the state the trade got to and the words to load
+fn outcome(mut state: TradeState, pool: &V3State) -> Result<TradeOutcome, TradeError> {
+    while state.remaining > U256::ZERO && state.x96price != state.price_limit {
+        let err = match step_start(&mut state, &pool.ticks) {
+            Ok(()) => None,
+            Err(TradeError::Tick(err)) => Some(err),
+            Err(err) => return Err(err),
+        };
+        let missing_words = match err {
+            // the next loaded tick is only the next tick of the pool when every word
+            // up to it is loaded
+            None => unloaded_words(&state, pool),
+            // the tick is in a loaded word but its liquidity wasn't read, the word is
+            // loaded again
+            Some(TickError::Unavailable { tick }) => {
+                vec![get_pos_from_tick(tick, pool.tick_spacing)]
+            }
+            Some(TickError::Overflow { .. } | TickError::Underflow { .. }) => {
+                next_missing_word(&state, pool)
+            }
+        };
+        if err.is_some() || !missing_words.is_empty() {
+            return Ok(TradeOutcome::Partial {
+                state,
+                missing_words,
+            });
+        }
+        swap_step(&mut state)?;
+        update_state_for_next_step(&mut state)?;
+    }
+    Ok(TradeOutcome::Filled(state))
+}
+
+/// Word the search for the next tick of `state` starts in, a swap up looks at ticks
+/// above the current one, a swap down at ticks at or below it
+fn start_word(state: &TradeState, tick_spacing: I24) -> i16 {
+    let compressed = normalize_tick(state.tick, tick_spacing);
+    word_index(if state.from0 {
+        compressed
+    } else {
+        compressed + I24::ONE
+    })
+}
+
+/// Words between the current tick of `state` and the next tick of its step that aren't
+/// loaded, a tick in one of them would come before the step's tick
+fn unloaded_words(state: &TradeState, pool: &V3State) -> Vec<i16> {
+    let start = start_word(state, pool.tick_spacing);
+    let target = get_pos_from_tick(state.step.next_tick.tick, pool.tick_spacing);
+    let (low, high) = if state.from0 {
+        (target, start)
+    } else {
+        (start, target)
+    };
+    (low..=high).filter(|w| !pool.words.contains(w)).collect()
+}
+
+/// First word past the current tick of `state` that isn't loaded, in the direction the
+/// price moves
+fn next_missing_word(state: &TradeState, pool: &V3State) -> Vec<i16> {
+    let (min, max) = word_bounds(pool.tick_spacing);
+
+    let mut pos = start_word(state, pool.tick_spacing);
+    while pool.words.contains(&pos) {
+        pos = match if state.from0 {
+            pos.checked_sub(1)
+        } else {
+            pos.checked_add(1)
+        } {
+            Some(pos) => pos,
+            None => return Vec::new(),
+        };
+    }
+    if (min..=max).contains(&pos) {
+        vec![pos]
+    } else {
+        Vec::new()
+    }
 }
 //////////////////////////////
 pub fn trade_start(
@@ -32,65 +131,115 @@ pub fn trade_start(
     fee: &U24,
     amount_in: U256,
     from0: bool,
+    sqrt_price_limit: Option<U256>,
+) -> Result<TradeState, TradeError> {
+    new_trade_state(pool, fee, amount_in, from0, true, sqrt_price_limit)
+}
+
+pub fn trade_start_exact_out(
+    pool: &V3State,
+    fee: &U24,
+    amount_out: U256,
+    from0: bool,
+    sqrt_price_limit: Option<U256>,
+) -> Result<TradeState, TradeError> {
+    new_trade_state(pool, fee, amount_out, from0, false, sqrt_price_limit)
+}
+
+fn new_trade_state(
+    pool: &V3State,
+    fee: &U24,
+    amount: U256,
+    from0: bool,
+    exact_in: bool,
+    sqrt_price_limit: Option<U256>,
 ) -> Result<TradeState, TradeError> {
-    let mut trade_state = TradeState {
+    // no limit is the closest price to the bounds a swap can be asked to stop at
+    let price_limit = match sqrt_price_limit {
+        Some(limit) => limit,
+        None if from0 => MIN_SQRT_RATIO + U256::ONE,
+        None => MAX_SQRT_RATIO - U256::ONE,
+    };
+
+    // the fee is taken on every step like the pool contract does, not up front
+    let trade_state = TradeState {
+        fee: *fee,
         fee_amount: U256::ZERO,
-        remaining: amount_in.clone(),
+        remaining: amount,
         amount_out: U256::ZERO,
         x96price: pool.x96price,
         liquidity: pool.liquidity,
-        amount_in,
+        amount_in: U256::ZERO,
         tick: pool.tick,
         from0,
+        exact_in,
+        price_limit,
         step: TradeStep::default(),
     };
-    let fee_amount = amount_in
-        .checked_mul(U256::from(*fee))
-        .ok_or(MathError::A(trade_state))?
-        .checked_div(U256::from(1_000_000))
-        .ok_or(MathError::A(trade_state))?;
-    trade_state.remaining = amount_in
-        .checked_sub(fee_amount)
-        .ok_or(MathError::A(trade_state))?;
-
-    trade_state.fee_amount = fee_amount;
-    trade_state.x96price = pool.x96price;
-    trade_state.tick = tick_from_price(pool.x96price).ok_or(MathError::A(trade_state))?;
-    trade_state.liquidity = pool.liquidity;
-    match pool.ticks.get_tick(trade_state.tick) {
-        Ok(_) => return Ok(trade_state),
-        Err(_) => {
-            trade_state.step.next_tick.tick = trade_state.tick; //just to work with the error
-            return Err(TickError::Overflow(trade_state).into());
-        }
+
+    // the limit has to be on the side the price moves to and within the bounds, the
+    // pool reverts with SPL otherwise
+    let valid = if from0 {
+        price_limit < pool.x96price && price_limit > MIN_SQRT_RATIO
+    } else {
+        price_limit > pool.x96price && price_limit < MAX_SQRT_RATIO
+    };
+    if !valid {
+        return Err(math_error(
+            &trade_state,
+            TradeStage::Start,
+            MathError::InvalidPriceLimit {
+                sqrt_price: pool.x96price,
+                sqrt_price_limit: price_limit,
+            },
+        ));
     }
+    Ok(trade_state)
 }
+
+/// Math error of `trade_state` at the tick it is on
+fn math_error(trade_state: &TradeState, stage: TradeStage, error: MathError) -> TradeError {
+    TradeError::Math {
+        stage,
+        tick: trade_state.tick,
+        error,
+    }
+}
+
 pub fn step_start(trade_state: &mut TradeState, ticks: &Ticks) -> Result<(), TradeError> {
+    // selling token0 moves the price down onto the closest tick at or below the
+    // current one, selling token1 moves it up onto the closest tick above
     trade_state.step.next_tick_index = match ticks.get_tick_index(trade_state.tick) {
         Ok(i) => {
             if trade_state.from0 {
+                i
+            } else {
                 if i + 1 >= ticks.len() {
-                    return Err(TickError::Overflow(*trade_state).into());
+                    return Err(TickError::Overflow {
+                        tick: trade_state.tick,
+                    }
+                    .into());
                 } // No ticks above
                 i + 1
-            } else {
-                if i == 0 {
-                    return Err(TickError::Underflow(*trade_state).into());
-                } // No ticks below
-                i - 1
             }
         }
         Err(i) => {
             if trade_state.from0 {
-                if i >= ticks.len() {
-                    return Err(TickError::Overflow(*trade_state).into());
-                } // No ticks above
-                i
-            } else {
                 if i == 0 {
-                    return Err(TickError::Underflow(*trade_state).into());
+                    return Err(TickError::Underflow {
+                        tick: trade_state.tick,
+                    }
+                    .into());
                 } // No ticks below
                 i - 1
+            } else {
+                if i >= ticks.len() {
+                    return Err(TickError::Overflow {
+                        tick: trade_state.tick,
+                    }
+                    .into());
+                } // No ticks above
+                i
             }
         }
     };
@@ -99,154 +248,545 @@ pub fn step_start(trade_state: &mut TradeState, ticks: &Ticks) -> Result<(), Tra
         .get(trade_state.step.next_tick_index)
         .expect("checked above");
 
+    let next_tick = trade_state.step.next_tick.tick;
     if trade_state.step.next_tick.liquidity_net.is_none() {
-        return Err(TickError::Unavailable(*trade_state).into());
+        return Err(TickError::Unavailable { tick: next_tick }.into());
     }
     // calculate the next tick’s price
-    trade_state.step.next_price =
-        price_from_tick(trade_state.step.next_tick.tick).ok_or(MathError::A(*trade_state))?;
-
-    // compute max amount possible to cross this tick
-    trade_state.step.amount_possible = get_delta(
-        trade_state.from0,
-        trade_state.liquidity,
-        trade_state.x96price,
-        trade_state.step.next_price,
-    )
-    .ok_or(MathError::A(*trade_state))?;
+    trade_state.step.next_price = price_from_tick(next_tick).ok_or_else(|| {
+        math_error(
+            trade_state,
+            TradeStage::StepStart,
+            MathError::TickOutOfRange { tick: next_tick },
+        )
+    })?;
+    trade_state.step.price_start = trade_state.x96price;
 
     Ok(())
 }
 
-////////////////////////////////////
-///
-pub fn get_delta(
-    from0: bool,
-    liquidity: U256,
-    current_price: U256,
-    next_price: U256,
-) -> Option<U256> {
-    // cross entire tick
-    let delta = if from0 {
-        let diff = next_price.checked_sub(current_price)?;
-        let (dif, liq) = (U512::from(diff), U512::from(liquidity));
-
-        U256::from(liq.checked_mul(dif)?.checked_div(U512::ONE << 96)?)
-    } else {
-        let diff = current_price.checked_sub(next_price)?;
-        let (dif, liq) = (U512::from(diff), U512::from(liquidity));
+/// Steps through the ticks until the specified amount is swapped or the price reaches
+/// the limit of the trade, whatever is left stays in `remaining`
+pub fn trade_loop(mut trade_state: TradeState, ticks: &Ticks) -> Result<TradeState, TradeError> {
+    run_loop(&mut trade_state, ticks)?;
+    Ok(trade_state)
+}
 
-        let den = U512::from(current_price.checked_mul(next_price)?);
-        let num = liq.checked_mul(dif)?;
+/// `trade_loop` leaving `trade_state` where the trade stopped, errors included
+fn run_loop(trade_state: &mut TradeState, ticks: &Ticks) -> Result<(), TradeError> {
+    while trade_state.remaining > U256::ZERO && trade_state.x96price != trade_state.price_limit {
+        step_start(trade_state, ticks)?;
+        swap_step(trade_state)?;
+        update_state_for_next_step(trade_state)?;
+    }
+    Ok(())
+}
 
-        let div = num.checked_div(den)?;
-        let r = div.checked_mul(U512::ONE << 96)?;
+/// Swaps as much of the remaining amount as the range up to the next tick, or the
+/// price limit, takes
+pub fn swap_step(trade_state: &mut TradeState) -> Result<(), TradeError> {
+    let fail = |trade_state: &TradeState, error: MathError| {
+        math_error(trade_state, TradeStage::SwapStep, error)
+    };
 
-        U256::from(r)
+    let liquidity = u128::try_from(trade_state.liquidity).map_err(|_| {
+        fail(
+            trade_state,
+            MathError::LiquidityTooLarge {
+                liquidity: trade_state.liquidity,
+            },
+        )
+    })?;
+    let remaining = I256::try_from(trade_state.remaining).map_err(|_| {
+        fail(
+            trade_state,
+            MathError::AmountTooLarge {
+                amount: trade_state.remaining,
+            },
+        )
+    })?;
+    // SwapMath tells exact output apart by a negative amount
+    let remaining = if trade_state.exact_in {
+        remaining
+    } else {
+        -remaining
     };
 
-    Some(delta)
-}
+    // the step stops at the limit when it comes before the next tick
+    let target = if trade_state.from0 {
+        trade_state.step.next_price.max(trade_state.price_limit)
+    } else {
+        trade_state.step.next_price.min(trade_state.price_limit)
+    };
+    let step = compute_swap_step(
+        trade_state.x96price,
+        target,
+        liquidity,
+        remaining,
+        trade_state.fee,
+    )
+    .map_err(|error| fail(trade_state, error))?;
 
-pub fn trade_loop(mut trade_state: TradeState, ticks: &Ticks) -> Result<TradeState, TradeError> {
-    while trade_state.remaining > U256::ZERO {
-        step_start(&mut trade_state, ticks)?;
-        println!("hi");
-        if trade_state.remaining < trade_state.step.amount_possible {
-            println!("not enough to cross finishing it");
-            handle_non_crossing_step(&mut trade_state)?;
-            break;
-        }
+    trade_state.step.amount_in = step.amount_in;
+    trade_state.step.amount_out = step.amount_out;
+    trade_state.step.fee_amount = step.fee_amount;
 
-        println!("gettiing delta to cross");
-        if let Some(delta) = get_delta(
-            trade_state.from0,
-            trade_state.liquidity,
-            trade_state.x96price,
-            trade_state.step.next_price,
-        ) {
-            trade_state.step.delta = delta;
-        } else {
-            return Err(TradeError::Math(MathError::A(trade_state)));
-        }
+    let add = |trade_state: &TradeState, a: U256, b: U256| {
+        a.checked_add(b)
+            .ok_or_else(|| fail(trade_state, MathError::AddOverflow { a, b }))
+    };
 
-        println!("prepping next step");
+    let paid = add(trade_state, step.amount_in, step.fee_amount)?;
+    let consumed = if trade_state.exact_in {
+        paid
+    } else {
+        step.amount_out
+    };
+    trade_state.remaining = trade_state.remaining.checked_sub(consumed).ok_or_else(|| {
+        fail(
+            trade_state,
+            MathError::SubUnderflow {
+                a: trade_state.remaining,
+                b: consumed,
+            },
+        )
+    })?;
+    trade_state.amount_in = add(trade_state, trade_state.amount_in, paid)?;
+    trade_state.amount_out = add(trade_state, trade_state.amount_out, step.amount_out)?;
+    trade_state.fee_amount = add(trade_state, trade_state.fee_amount, step.fee_amount)?;
+    trade_state.x96price = step.sqrt_price_next;
 
-        update_state_for_next_step(&mut trade_state)?;
-    }
-    Ok(trade_state)
+    Ok(())
 }
 
+/// Crosses the next tick when the step reached its price, otherwise moves the current
+/// tick to the price the step stopped at
 pub fn update_state_for_next_step(trade_state: &mut TradeState) -> Result<(), TradeError> {
-    trade_state.amount_out = trade_state
-        .amount_out
-        .checked_add(trade_state.step.delta)
-        .ok_or(MathError::A(*trade_state))?;
-    // update liquidity
-    if let Some(net) = trade_state.step.next_tick.liquidity_net {
-        trade_state.liquidity = if trade_state.from0 {
-            if net > 0 {
-                trade_state.liquidity.saturating_add(U256::from(net))
-            } else {
-                trade_state.liquidity.saturating_sub(U256::from(-net))
-            }
+    if trade_state.x96price == trade_state.step.next_price {
+        let next = trade_state.step.next_tick;
+        let net = next
+            .liquidity_net
+            .ok_or(TickError::Unavailable { tick: next.tick })?;
+        // liquidity_net is what crossing the tick upwards adds, removing i128::MIN
+        // would need more than the 128 bits liquidity has
+        let liquidity = trade_state.liquidity;
+        let net = if trade_state.from0 {
+            net.checked_neg().ok_or_else(|| {
+                math_error(
+                    trade_state,
+                    TradeStage::CrossTick,
+                    MathError::LiquidityUnderflow {
+                        liquidity,
+                        delta: net,
+                    },
+                )
+            })?
         } else {
-            if net < 0 {
-                trade_state.liquidity.saturating_add(U256::from(net))
+            net
+        };
+        trade_state.liquidity = update_liquidity(liquidity, net).ok_or_else(|| {
+            let error = if net < 0 {
+                MathError::LiquidityUnderflow {
+                    liquidity,
+                    delta: net,
+                }
             } else {
-                trade_state.liquidity.saturating_sub(U256::from(net))
-            }
+                MathError::LiquidityOverflow {
+                    liquidity,
+                    delta: net,
+                }
+            };
+            math_error(trade_state, TradeStage::CrossTick, error)
+        })?;
+        trade_state.tick = if trade_state.from0 {
+            next.tick - I24::ONE
+        } else {
+            next.tick
         };
+    } else if trade_state.x96price != trade_state.step.price_start {
+        trade_state.tick = tick_from_price(trade_state.x96price).ok_or_else(|| {
+            math_error(
+                trade_state,
+                TradeStage::CrossTick,
+                MathError::PriceOutOfBounds {
+                    sqrt_price: trade_state.x96price,
+                },
+            )
+        })?;
     }
-    trade_state.tick = trade_state.step.next_tick.tick;
-    trade_state.x96price = trade_state.step.next_price;
-    trade_state.remaining = trade_state
-        .remaining
-        .checked_sub(trade_state.step.amount_possible)
-        .ok_or(MathError::A(*trade_state))?;
 
     Ok(())
 }
-/////////////////////////////////////
-pub fn handle_non_crossing_step(trade_state: &mut TradeState) -> Result<(), TradeError> {
-    // won't cross full tick
-    let new_price = if trade_state.from0 {
-        compute_price_from0(
-            &trade_state.remaining,
-            &trade_state.liquidity,
-            &trade_state.x96price,
-            true,
-        )
-        .ok_or(MathError::A(*trade_state))? //deference &mut let it use copy
-    } else {
-        compute_price_from1(
-            &trade_state.remaining,
-            &trade_state.liquidity,
-            &trade_state.x96price,
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+    use crate::v3_base::{tick_math::MAX_TICK, ticks::Tick};
+
+    fn pool(liquidity: u128) -> V3State {
+        let net = i128::try_from(liquidity).unwrap();
+        V3State {
+            tick: I24::ZERO,
+            ticks: Ticks::new(vec![
+                Tick {
+                    tick: I24::try_from(-600).unwrap(),
+                    liquidity_net: Some(net),
+                },
+                Tick {
+                    tick: I24::try_from(600).unwrap(),
+                    liquidity_net: Some(-net),
+                },
+            ]),
+            liquidity: U256::from(liquidity),
+            x96price: U256::ONE << 96,
+            tick_spacing: I24::try_from(60).unwrap(),
+            words: BTreeSet::from([-1, 0]),
+        }
+    }
+
+    fn filled(outcome: Result<TradeOutcome, TradeError>) -> TradeState {
+        match outcome {
+            Ok(TradeOutcome::Filled(state)) => state,
+            other => panic!("expected a filled trade, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn trade_within_range_matches_swap_step() {
+        let liquidity = 2 * 10u128.pow(18);
+        let amount_in = U256::from(10u64.pow(15));
+        let fee = U24::from(3000);
+
+        for from0 in [true, false] {
+            let target =
+                price_from_tick(I24::try_from(if from0 { -600 } else { 600 }).unwrap()).unwrap();
+            let step = compute_swap_step(
+                U256::ONE << 96,
+                target,
+                liquidity,
+                I256::try_from(amount_in).unwrap(),
+                fee,
+            )
+            .unwrap();
+
+            let result = filled(trade(&pool(liquidity), &fee, amount_in, from0, None));
+            assert_eq!(result.amount_out, step.amount_out);
+            assert_eq!(result.fee_amount, step.fee_amount);
+            assert_eq!(result.x96price, step.sqrt_price_next);
+            assert_eq!(result.remaining, U256::ZERO);
+            assert_eq!(result.amount_in, amount_in);
+            assert_eq!(result.liquidity, U256::from(liquidity));
+            assert_eq!(result.tick, tick_from_price(step.sqrt_price_next).unwrap());
+        }
+    }
+
+    #[test]
+    fn running_out_of_ticks_reports_the_missing_word() {
+        let amount_in = U256::from(10u64.pow(18));
+        let fee = U24::from(3000);
+        let mut pool = pool(10u128.pow(18));
+        let Ok(TradeOutcome::Partial {
+            state,
+            missing_words,
+        }) = trade(&pool, &fee, amount_in, true, None)
+        else {
+            panic!("expected the trade to run out of ticks below");
+        };
+
+        // the range was swapped through and the liquidity left with it
+        assert_eq!(state.liquidity, U256::ZERO);
+        assert_eq!(state.tick, I24::try_from(-601).unwrap());
+        assert!(state.remaining > U256::ZERO);
+        assert_eq!(state.amount_in + state.remaining, amount_in);
+        assert_eq!(missing_words, vec![-2]);
+
+        // load the word and pick the trade up where it stopped
+        let deep = 10i128.pow(22);
+        pool.ticks.insert_ticks(vec![
+            Tick {
+                tick: I24::try_from(-15480).unwrap(),
+                liquidity_net: Some(deep),
+            },
+            Tick {
+                tick: I24::try_from(-15420).unwrap(),
+                liquidity_net: Some(-deep),
+            },
+        ]);
+        pool.words.insert(-2);
+        let done = filled(retry(state, &pool));
+        assert_eq!(done.remaining, U256::ZERO);
+        assert_eq!(done.amount_in, amount_in);
+        assert!(done.amount_out > state.amount_out);
+        assert_eq!(done.liquidity, U256::from(deep.unsigned_abs()));
+    }
+
+    #[test]
+    fn missing_words_up_and_unavailable_ticks() {
+        let fee = U24::from(3000);
+        let mut pool = pool(10u128.pow(18));
+        let amount_in = U256::from(10u64.pow(18));
+
+        let Ok(TradeOutcome::Partial { missing_words, .. }) =
+            trade(&pool, &fee, amount_in, false, None)
+        else {
+            panic!("expected the trade to run out of ticks above");
+        };
+        assert_eq!(missing_words, vec![1]);
+
+        // a tick whose liquidity wasn't read has its word loaded again
+        pool.ticks.insert_ticks(vec![Tick {
+            tick: I24::try_from(300).unwrap(),
+            liquidity_net: None,
+        }]);
+        let Ok(TradeOutcome::Partial {
+            state,
+            missing_words,
+        }) = trade(&pool, &fee, amount_in, false, None)
+        else {
+            panic!("expected the trade to stop at the unavailable tick");
+        };
+        assert_eq!(missing_words, vec![0]);
+        assert_eq!(state.tick, I24::ZERO);
+    }
+
+    #[test]
+    fn trade_outside_the_loaded_words_reports_the_words_it_skips() {
+        let fee = U24::from(3000);
+        let liquidity = 10u128.pow(18);
+        let mut pool = pool(liquidity);
+        // the price moved three words up since the words were loaded, out of the range
+        pool.tick = I24::try_from(60 * 256 * 3 + 60).unwrap();
+        pool.x96price = price_from_tick(pool.tick).unwrap();
+        pool.liquidity = U256::ZERO;
+        let amount_in = U256::from(10u64.pow(15));
+
+        let Ok(TradeOutcome::Partial {
+            state,
+            missing_words,
+        }) = trade(&pool, &fee, amount_in, true, None)
+        else {
+            panic!("expected the trade to stop before the unloaded words");
+        };
+        assert_eq!(missing_words, vec![1, 2, 3]);
+        assert_eq!(state.tick, pool.tick);
+        assert_eq!(state.amount_in, U256::ZERO);
+
+        // the words hold no ticks, the trade goes down to the range and fills in it
+        pool.words.extend([1, 2, 3]);
+        let done = filled(retry(state, &pool));
+        assert_eq!(done.remaining, U256::ZERO);
+        assert_eq!(done.liquidity, U256::from(liquidity));
+        assert!(done.tick < I24::try_from(600).unwrap());
+    }
+
+    #[test]
+    fn exact_out_within_range_matches_swap_step() {
+        let liquidity = 2 * 10u128.pow(18);
+        let amount_out = U256::from(10u64.pow(15));
+        let fee = U24::from(3000);
+
+        for from0 in [true, false] {
+            let target =
+                price_from_tick(I24::try_from(if from0 { -600 } else { 600 }).unwrap()).unwrap();
+            let step = compute_swap_step(
+                U256::ONE << 96,
+                target,
+                liquidity,
+                -I256::try_from(amount_out).unwrap(),
+                fee,
+            )
+            .unwrap();
+
+            let result = filled(trade_exact_out(
+                &pool(liquidity),
+                &fee,
+                amount_out,
+                from0,
+                None,
+            ));
+            assert_eq!(result.amount_out, amount_out);
+            assert_eq!(result.amount_in, step.amount_in + step.fee_amount);
+            assert_eq!(result.fee_amount, step.fee_amount);
+            assert_eq!(result.x96price, step.sqrt_price_next);
+            assert_eq!(result.remaining, U256::ZERO);
+
+            // paying that input back in exact input mode gets at least the output asked
+            let back = filled(trade(&pool(liquidity), &fee, result.amount_in, from0, None));
+            assert!(back.amount_out >= amount_out);
+        }
+    }
+
+    #[test]
+    fn exact_out_crosses_ticks() {
+        let liquidity = 10u128.pow(18);
+        let mut pool = pool(liquidity);
+        pool.ticks.insert_ticks(vec![
+            Tick {
+                tick: I24::try_from(-1200).unwrap(),
+                liquidity_net: Some(i128::try_from(liquidity).unwrap()),
+            },
+            Tick {
+                tick: I24::try_from(-60).unwrap(),
+                liquidity_net: Some(i128::try_from(liquidity).unwrap()),
+            },
+        ]);
+        pool.liquidity = U256::from(2 * liquidity);
+
+        let amount_out = U256::from(2 * 10u64.pow(16));
+        let result = filled(trade_exact_out(
+            &pool,
+            &U24::from(500),
+            amount_out,
             true,
+            None,
+        ));
+        assert_eq!(result.amount_out, amount_out);
+        assert_eq!(result.remaining, U256::ZERO);
+        // crossed -60 going down, leaving the liquidity of the -600 range
+        assert_eq!(result.liquidity, U256::from(liquidity));
+        assert!(result.tick < I24::try_from(-60).unwrap());
+
+        let back = filled(trade(&pool, &U24::from(500), result.amount_in, true, None));
+        assert!(back.amount_out >= amount_out);
+    }
+
+    #[test]
+    fn trade_stops_at_price_limit() {
+        let liquidity = 10u128.pow(18);
+        let amount_in = U256::from(10u64.pow(17));
+        let fee = U24::from(3000);
+        let limit = price_from_tick(I24::try_from(-30).unwrap()).unwrap();
+
+        let result = filled(trade(&pool(liquidity), &fee, amount_in, true, Some(limit)));
+        assert_eq!(result.x96price, limit);
+        assert!(result.remaining > U256::ZERO);
+        assert_eq!(result.amount_in + result.remaining, amount_in);
+        assert_eq!(result.tick, I24::try_from(-30).unwrap());
+
+        let step = compute_swap_step(
+            U256::ONE << 96,
+            limit,
+            liquidity,
+            I256::try_from(amount_in).unwrap(),
+            fee,
         )
-        .ok_or(MathError::A(*trade_state))?
-    };
+        .unwrap();
+        assert_eq!(result.amount_in, step.amount_in + step.fee_amount);
+        assert_eq!(result.amount_out, step.amount_out);
 
-    let delta = get_delta(
-        trade_state.from0,
-        trade_state.liquidity,
-        trade_state.x96price,
-        trade_state.step.next_price,
-    )
-    .unwrap();
+        let receipt = result.receipt(Default::default(), Default::default(), Default::default());
+        assert_eq!(receipt.remaining, result.remaining);
+    }
 
-    trade_state.step.delta = delta;
+    #[test]
+    fn price_limit_is_validated() {
+        let pool = pool(10u128.pow(18));
+        let fee = U24::from(3000);
+        let amount = U256::from(1000);
 
-    println!("{}", trade_state.step.delta);
+        // on the wrong side of the current price
+        let above = U256::ONE << 97;
+        assert_eq!(
+            trade(&pool, &fee, amount, true, Some(above)).err(),
+            Some(TradeError::Math {
+                stage: TradeStage::Start,
+                tick: I24::ZERO,
+                error: MathError::InvalidPriceLimit {
+                    sqrt_price: U256::ONE << 96,
+                    sqrt_price_limit: above,
+                },
+            })
+        );
+        assert!(trade(&pool, &fee, amount, false, Some(above)).is_ok());
+        // at the bounds
+        assert!(trade(&pool, &fee, amount, true, Some(MIN_SQRT_RATIO)).is_err());
+        assert!(trade(&pool, &fee, amount, false, Some(MAX_SQRT_RATIO)).is_err());
+        assert!(trade_exact_out(&pool, &fee, amount, true, Some(MIN_SQRT_RATIO)).is_err());
+    }
 
-    println!("be");
-    trade_state.amount_out = trade_state
-        .amount_out
-        .checked_add(U256::from(delta))
-        .ok_or(MathError::A(*trade_state))?;
-    trade_state.remaining = U256::ZERO;
-    trade_state.x96price = U256::from(new_price);
+    #[test]
+    fn math_errors_carry_stage_tick_and_inputs() {
+        let liquidity = 10u128.pow(18);
+        let fee = U24::from(3000);
+        let amount = U256::from(10u128.pow(24));
 
-    Ok(())
+        // crossing -600 down takes out more liquidity than the pool has in range
+        let mut thin = pool(liquidity);
+        thin.liquidity = U256::from(liquidity / 2);
+        let err = trade(&thin, &fee, amount, true, None).unwrap_err();
+        assert_eq!(
+            err,
+            TradeError::Math {
+                stage: TradeStage::CrossTick,
+                tick: I24::ZERO,
+                error: MathError::LiquidityUnderflow {
+                    liquidity: U256::from(liquidity / 2),
+                    delta: -i128::try_from(liquidity).unwrap(),
+                },
+            }
+        );
+
+        let mut wide = pool(liquidity);
+        wide.liquidity = U256::from(u128::MAX) + U256::ONE;
+        let err = trade_exact_out(&wide, &fee, amount, false, None).unwrap_err();
+        assert_eq!(
+            err,
+            TradeError::Math {
+                stage: TradeStage::SwapStep,
+                tick: I24::ZERO,
+                error: MathError::LiquidityTooLarge {
+                    liquidity: wide.liquidity,
+                },
+            }
+        );
+
+        // a tick past the range prices can take
+        let mut far = pool(liquidity);
+        let past_max = I24::try_from(MAX_TICK + 8).unwrap();
+        far.ticks = Ticks::new(vec![Tick {
+            tick: past_max,
+            liquidity_net: Some(0),
+        }]);
+        let err = trade(&far, &fee, amount, false, None).unwrap_err();
+        assert_eq!(
+            err,
+            TradeError::Math {
+                stage: TradeStage::StepStart,
+                tick: I24::ZERO,
+                error: MathError::TickOutOfRange { tick: past_max },
+            }
+        );
+    }
+
+    #[test]
+    fn trade_errors_display_and_chain_their_source() {
+        use std::error::Error;
+
+        let error = MathError::LiquidityUnderflow {
+            liquidity: U256::from(500),
+            delta: -1000,
+        };
+        let err = TradeError::Math {
+            stage: TradeStage::CrossTick,
+            tick: I24::try_from(-60).unwrap(),
+            error,
+        };
+        assert_eq!(
+            err.to_string(),
+            "tick cross failed at tick -60: liquidity 500 -1000 underflows"
+        );
+        assert_eq!(err.source().unwrap().to_string(), error.to_string());
+
+        let err = TradeError::from(TickError::Overflow {
+            tick: I24::try_from(600).unwrap(),
+        });
+        assert_eq!(
+            err.to_string(),
+            "trade ran out of ticks: no loaded tick above 600"
+        );
+        assert!(err.source().is_some());
+        assert!(TradeError::V2.source().is_none());
+    }
 }
@@ -1,3 +1,5 @@
+use std::collections::BTreeSet;
+
 use alloy_primitives::{aliases::I24, ruint::aliases::U256};
 
 use super::ticks::Ticks;
@@ -8,4 +10,8 @@ pub struct V3State {
     pub ticks: Ticks,
     pub liquidity: U256,
     pub x96price: U256,
+    pub tick_spacing: I24,
+    /// bitmap words `ticks` was read from, a trade running past them reports the next
+    /// one as missing
+    pub words: BTreeSet<i16>,
 }
@@ -0,0 +1,167 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use alloy::{
+    providers::Provider,
+    rpc::types::{Filter, Log},
+};
+use chains_json::chains::ChainsJsonInput;
+use cortex::{cortex::WsProvider, generate_fallback_provider};
+use futures::{
+    StreamExt,
+    channel::mpsc::{UnboundedReceiver, UnboundedSender},
+};
+
+use crate::{
+    master_context::MasterContext,
+    watched_events,
+    ws_supervisor::{Backoff, BlockGap, GapRequest},
+};
+
+/// Times a single block is requested before the rest of its gap is requeued
+const BLOCK_ATTEMPTS: u32 = 5;
+
+/// Times the rest of a gap is requeued before its blocks are given up on
+const GAP_RETRIES: u32 = 3;
+
+/// Replays missed block ranges through `eth_getLogs` on the http nodes of each chain
+pub struct Backfiller {
+    providers: HashMap<u64, WsProvider>,
+    ctx: Arc<MasterContext>,
+    /// max blocks per `eth_getLogs` request, halved while the node refuses the range
+    chunk_size: u64,
+    /// where blocks that keep failing are sent back to, behind the other gaps
+    requeue: UnboundedSender<GapRequest>,
+}
+
+impl Backfiller {
+    pub fn new(
+        chains: &ChainsJsonInput,
+        ctx: Arc<MasterContext>,
+        chunk_size: u64,
+        requeue: UnboundedSender<GapRequest>,
+    ) -> Self {
+        let mut providers = HashMap::new();
+        for (chain_id, chain) in chains.chains.iter() {
+            if let Some(provider) = generate_fallback_provider(chain.http_nodes_urls.clone()) {
+                providers.insert(*chain_id, provider);
+            }
+        }
+
+        Self {
+            providers,
+            ctx,
+            chunk_size: chunk_size.max(1),
+            requeue,
+        }
+    }
+
+    /// Backfills the gaps one after the other, telling the listener of each one when it
+    /// is done. A gap stopped by a block that keeps failing is requeued from that block,
+    /// behind the other gaps and with the listener still waiting on it, up to
+    /// `GAP_RETRIES` times
+    pub async fn run(self, mut gaps: UnboundedReceiver<GapRequest>) {
+        while let Some(request) = gaps.next().await {
+            let Some(rest) = self.backfill(request.gap).await else {
+                if let Some(done) = request.done {
+                    let _ = done.send(());
+                }
+                continue;
+            };
+
+            if request.retries >= GAP_RETRIES {
+                println!(
+                    "chain {} blocks {}..={} could not be backfilled after {} retries, their logs are lost",
+                    rest.chain, rest.from, rest.to, GAP_RETRIES
+                );
+                if let Some(done) = request.done {
+                    let _ = done.send(());
+                }
+                continue;
+            }
+
+            let retry = GapRequest {
+                gap: rest,
+                done: request.done,
+                retries: request.retries + 1,
+            };
+            if let Err(err) = self.requeue.unbounded_send(retry) {
+                println!("error requeueing block gap {:?}", err);
+            }
+        }
+    }
+
+    /// Applies every pool log of the gap in block / log index order. Stops at the first
+    /// block the node keeps failing for, nothing past it is applied or marked, and
+    /// returns the rest of the gap from that block
+    pub async fn backfill(&self, gap: BlockGap) -> Option<BlockGap> {
+        let Some(provider) = self.providers.get(&gap.chain) else {
+            println!("no http node to backfill chain {}", gap.chain);
+            return None;
+        };
+
+        let filter = Filter::new().events(watched_events());
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(16));
+        let mut attempts = 0;
+
+        let mut from = gap.from;
+        let mut span = self.chunk_size;
+        while from <= gap.to {
+            let to = from.saturating_add(span - 1).min(gap.to);
+            match provider
+                .get_logs(&filter.clone().from_block(from).to_block(to))
+                .await
+            {
+                Ok(mut logs) => {
+                    logs.sort_by_key(|l: &Log| (l.block_number, l.log_index));
+                    for log in logs.iter() {
+                        self.ctx.tokens().handle_log(gap.chain, log);
+                        if let Some(r) = self.ctx.decode_pool_log(log) {
+                            r.handle(&self.ctx, gap.chain);
+                        }
+                    }
+
+                    println!(
+                        "chain {} backfilled blocks {}..={} ({} logs)",
+                        gap.chain,
+                        from,
+                        to,
+                        logs.len()
+                    );
+                    self.ctx.mark_block(gap.chain, to);
+                    from = to + 1;
+                    span = self.chunk_size;
+                    attempts = 0;
+                    backoff.reset();
+                }
+                Err(err) if span > 1 => {
+                    println!(
+                        "chain {} get_logs {}..={} failed, splitting range: {:?}",
+                        gap.chain, from, to, err
+                    );
+                    span /= 2;
+                }
+                Err(err) if attempts + 1 < BLOCK_ATTEMPTS => {
+                    attempts += 1;
+                    let delay = backoff.next_delay();
+                    println!(
+                        "chain {} get_logs for block {} failed, retrying in {:?}: {:?}",
+                        gap.chain, from, delay, err
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                Err(err) => {
+                    println!(
+                        "chain {} get_logs for block {} failed {} times, requeueing blocks {}..={}: {:?}",
+                        gap.chain, from, BLOCK_ATTEMPTS, from, gap.to, err
+                    );
+                    return Some(BlockGap {
+                        chain: gap.chain,
+                        from,
+                        to: gap.to,
+                    });
+                }
+            }
+        }
+        None
+    }
+}
@@ -2,126 +2,353 @@ use std::collections::BTreeMap;
 
 use all_sol_types::sol_types::{
     IERC1155::balanceOfReturn,
-    IPoolManager::IPoolManagerCalls,
+    IPoolManager::{self, IPoolManagerCalls, IPoolManagerInstance},
     IPositionManager::IPositionManagerInstance,
-    IUniswapV2Pair::{IUniswapV2PairInstance, getReservesReturn},
+    IUniswapV2Factory::{IUniswapV2FactoryInstance, allPairsCall},
+    IUniswapV2Pair::{self, getReservesReturn},
     PoolKey,
-    StateView::{StateViewInstance, getSlot0Return},
-    V3Pool::{V3PoolInstance, slot0Return},
+    StateView::{self, StateViewInstance, getSlot0Return},
+    V3Pool::{self, slot0Return},
 };
-use alloy::primitives::{B256, aliases::I24};
-use alloy::providers::Provider;
-use alloy_sol_types::SolValue;
-use futures::{
-    future::join_all,
-    stream::{FuturesOrdered, FuturesUnordered, StreamExt},
+use alloy::primitives::{
+    Address, B256, Bytes, U256,
+    aliases::{I24, U24},
+};
+use alloy::providers::{CallItem, Provider};
+use alloy::{
+    eips::{BlockId, BlockNumberOrTag},
+    rpc::types::{Filter, TransactionRequest},
+    transports::TransportResult,
+};
+use alloy_sol_types::{SolCall, SolEvent};
+use futures::future::join_all;
+use shape::{
+    p_state::SyncPoint,
+    p_ticks::{TickData, TicksBitMap},
 };
-use shape::p_ticks::{TickData, TicksBitMap};
 use v3::v3_base::bitmap_math;
 
-use crate::master_context::MasterContext;
+/// Default number of calls packed in a single `aggregate3` request
+pub const DEFAULT_MULTICALL_BATCH: usize = 500;
 
-async fn get_v2_reserves<P: Provider + Clone>(
-    pool: IUniswapV2PairInstance<P>,
-) -> Option<getReservesReturn> {
-    if let Ok(reserves) = pool.getReserves().call().await {
-        return Some(reserves);
-    };
-    None
+/// Executes calls of one kind through Multicall3 `aggregate3`, `batch_size` calls per
+/// request and every call allowed to fail on its own. When a whole request fails, e.g.
+/// on a chain without Multicall3, the batch falls back to one `eth_call` per call.
+/// Results always come back in the order the calls were given
+#[derive(Clone)]
+pub struct MulticallBatcher<P> {
+    provider: P,
+    pub batch_size: usize,
+    /// requests in flight at the same time
+    pub concurrency: usize,
+    /// block every read is pinned to, latest when unset
+    pub block: Option<BlockId>,
 }
 
-async fn get_v3_slot0<P: Provider + Clone>(pool: V3PoolInstance<P>) -> Option<slot0Return> {
-    if let Ok(slot0) = pool.slot0().call().await {
-        return Some(slot0);
-    };
-    None
+impl<P: Provider + Clone> MulticallBatcher<P> {
+    pub fn new(provider: P) -> Self {
+        Self {
+            provider,
+            batch_size: DEFAULT_MULTICALL_BATCH,
+            concurrency: 4,
+            block: None,
+        }
+    }
+
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    pub fn at_block(mut self, block: BlockId) -> Self {
+        self.block = Some(block);
+        self
+    }
+
+    pub async fn call<D: SolCall + 'static>(
+        &self,
+        calls: impl IntoIterator<Item = (Address, D)>,
+    ) -> Vec<Option<D::Return>> {
+        let calls: Vec<(Address, Bytes)> = calls
+            .into_iter()
+            .map(|(target, call)| (target, call.abi_encode().into()))
+            .collect();
+
+        let batch_size = self.batch_size.max(1);
+        let mut results = Vec::with_capacity(calls.len());
+        for group in calls.chunks(batch_size * self.concurrency.max(1)) {
+            let batches = join_all(
+                group
+                    .chunks(batch_size)
+                    .map(|batch| self.call_batch::<D>(batch)),
+            )
+            .await;
+            results.extend(batches.into_iter().flatten());
+        }
+        results
+    }
+
+    async fn call_batch<D: SolCall + 'static>(
+        &self,
+        batch: &[(Address, Bytes)],
+    ) -> Vec<Option<D::Return>> {
+        let mut multicall = self.provider.multicall().dynamic::<D>();
+        if let Some(block) = self.block {
+            multicall = multicall.block(block);
+        }
+        for (target, input) in batch {
+            multicall = multicall
+                .add_call_dynamic(CallItem::new(*target, input.clone()).with_failure_allowed());
+        }
+
+        if let Ok(results) = multicall.aggregate3().await {
+            return results.into_iter().map(Result::ok).collect();
+        }
+
+        join_all(
+            batch
+                .iter()
+                .map(|(target, input)| self.call_single::<D>(*target, input.clone())),
+        )
+        .await
+    }
+
+    async fn call_single<D: SolCall>(&self, target: Address, input: Bytes) -> Option<D::Return> {
+        let tx = TransactionRequest::default().to(target).input(input.into());
+        let mut call = self.provider.call(tx);
+        if let Some(block) = self.block {
+            call = call.block(block);
+        }
+        D::abi_decode_returns(&call.await.ok()?).ok()
+    }
 }
 
-async fn get_v3_liquidity<P: Provider + Clone>(
-    pool: V3PoolInstance<P>,
-    word: i16,
-    tick_spacing: I24,
-) -> Option<u128> {
-    if let Ok(liq) = pool.liquidity().call().await {
-        return Some(liq);
-    };
-    None
+/// Latest block of the chain, every read of a hydration batch is pinned to it so the
+/// pools come out as one consistent snapshot
+pub async fn get_sync_point<P: Provider>(provider: &P) -> Option<SyncPoint> {
+    let block = provider
+        .get_block_by_number(BlockNumberOrTag::Latest)
+        .await
+        .ok()??;
+    Some(SyncPoint::at_block(block.header.number, block.header.hash))
 }
 
-async fn get_v3_word_ticks<P: Provider + Clone>(
-    pool: V3PoolInstance<P>,
-    word: i16,
-    tick_spacing: I24,
-) -> Option<TicksBitMap> {
-    if let Ok(bitmap) = pool.tickBitmap(word).call().await {
-        let tks = bitmap_math::extract_ticks_from_bitmap(bitmap, word, tick_spacing);
-        let mut new_ticks_map = BTreeMap::<I24, TickData>::new();
-        let r: Option<TicksBitMap> = None;
-        {
-            let mut calls = FuturesOrdered::new();
-
-            for tick in &tks {
-                let pc = pool.clone();
-                calls.push_back(async move { pc.ticks(*tick).call().await });
-            }
+/// Block reads synced at `point` are made at, by hash when known so a reorg can't
+/// silently swap the block under a batch
+pub fn pinned_block(point: &SyncPoint) -> BlockId {
+    match point.hash {
+        Some(hash) => BlockId::hash(hash),
+        None => BlockId::number(point.block),
+    }
+}
 
-            let mut idx = 0;
-            while let Some(res) = calls.next().await {
-                let tick = tks[idx];
-                new_ticks_map.insert(
-                    tick,
-                    TickData {
-                        liquidity_net: res.ok().map(|x| x.liquidityNet),
-                    },
-                );
-            }
-        };
+/// Reserves, tokens and factory of each pair, batched across pairs
+pub async fn get_v2_pools<P: Provider + Clone>(
+    batcher: &MulticallBatcher<P>,
+    pools: &[Address],
+) -> Vec<Option<(getReservesReturn, Address, Address, Address)>> {
+    let (reserves, token0, token1, factory) = tokio::join!(
+        batcher.call(
+            pools
+                .iter()
+                .map(|p| (*p, IUniswapV2Pair::getReservesCall {}))
+        ),
+        batcher.call(pools.iter().map(|p| (*p, IUniswapV2Pair::token0Call {}))),
+        batcher.call(pools.iter().map(|p| (*p, IUniswapV2Pair::token1Call {}))),
+        batcher.call(pools.iter().map(|p| (*p, IUniswapV2Pair::factoryCall {}))),
+    );
 
-        return Some(TicksBitMap {
-            bitmap,
-            ticks: new_ticks_map,
-        });
-    };
-    None
+    reserves
+        .into_iter()
+        .zip(token0)
+        .zip(token1)
+        .zip(factory)
+        .map(|(((r, t0), t1), f)| Some((r?, t0?, t1?, f?)))
+        .collect()
 }
 
-async fn get_v4_word_ticks<P: Provider + Clone>(
-    pool: &StateViewInstance<P>,
-    word: i16,
-    pool_id: B256,
-    tick_spacing: I24,
-) -> Option<TicksBitMap> {
-    if let Ok(bitmap) = pool.getTickBitmap(pool_id, word).call().await {
-        let tks = bitmap_math::extract_ticks_from_bitmap(bitmap, word, tick_spacing);
-        let r: Option<TicksBitMap> = None;
+/// Config and state of each v3 pool: slot0, liquidity, token0, token1, fee, tick spacing
+/// and factory, batched across pools
+pub async fn get_v3_pools<P: Provider + Clone>(
+    batcher: &MulticallBatcher<P>,
+    pools: &[Address],
+) -> Vec<Option<(slot0Return, u128, Address, Address, U24, I24, Address)>> {
+    let (slot0, liquidity, token0, token1, fee, tick_spacing, factory) = tokio::join!(
+        batcher.call(pools.iter().map(|p| (*p, V3Pool::slot0Call {}))),
+        batcher.call(pools.iter().map(|p| (*p, V3Pool::liquidityCall {}))),
+        batcher.call(pools.iter().map(|p| (*p, V3Pool::token0Call {}))),
+        batcher.call(pools.iter().map(|p| (*p, V3Pool::token1Call {}))),
+        batcher.call(pools.iter().map(|p| (*p, V3Pool::feeCall {}))),
+        batcher.call(pools.iter().map(|p| (*p, V3Pool::tickSpacingCall {}))),
+        batcher.call(pools.iter().map(|p| (*p, V3Pool::factoryCall {}))),
+    );
 
-        let calls = FuturesUnordered::new();
+    slot0
+        .into_iter()
+        .zip(liquidity)
+        .zip(token0)
+        .zip(token1)
+        .zip(fee)
+        .zip(tick_spacing)
+        .zip(factory)
+        .map(|((((((s, l), t0), t1), f), ts), fa)| Some((s?, l?, t0?, t1?, f?, ts?, fa?)))
+        .collect()
+}
 
-        for tick in tks {
-            calls.push(pool.getTickInfo(pool_id, tick).call().await);
-        }
+/// Pairs `from..to` of a v2 factory
+pub async fn get_v2_pairs<P: Provider + Clone>(
+    factory: IUniswapV2FactoryInstance<P>,
+    from: u64,
+    to: u64,
+    block: BlockId,
+) -> Option<Vec<Address>> {
+    MulticallBatcher::new(factory.provider().clone())
+        .at_block(block)
+        .call((from..to).map(|i| (*factory.address(), allPairsCall(U256::from(i)))))
+        .await
+        .into_iter()
+        .collect()
+}
 
-        return r;
-    };
+/// Tick bitmap words `(pool, word, tick spacing)` together with the liquidity of every
+/// initialized tick in them: one round of batches for the bitmaps and one for the ticks,
+/// whatever the number of pools
+pub async fn get_v3_words<P: Provider + Clone>(
+    batcher: &MulticallBatcher<P>,
+    words: &[(Address, i16, I24)],
+) -> Vec<Option<TicksBitMap>> {
+    let bitmaps = batcher
+        .call(words.iter().map(|(pool, word, _)| {
+            (
+                *pool,
+                V3Pool::tickBitmapCall {
+                    wordPosition: *word,
+                },
+            )
+        }))
+        .await;
+    let ticks = initialized_ticks(
+        words.iter().map(|(_, word, spacing)| (*word, *spacing)),
+        &bitmaps,
+    );
 
-    None
+    let tick_calls: Vec<(Address, V3Pool::ticksCall)> = words
+        .iter()
+        .zip(&ticks)
+        .flat_map(|((pool, _, _), tks)| {
+            tks.iter()
+                .map(|tick| (*pool, V3Pool::ticksCall { tick: *tick }))
+        })
+        .collect();
+    let infos = batcher
+        .call(tick_calls)
+        .await
+        .into_iter()
+        .map(|res| res.map(|x| (x.liquidityNet, x.liquidityGross)));
+
+    assemble_words(bitmaps, ticks, infos)
 }
 
-pub async fn get_v4_slot0<P: Provider + Clone>(
-    pool: StateViewInstance<P>,
-    id: B256,
-) -> Option<getSlot0Return> {
-    if let Ok(slot0) = pool.getSlot0(id).call().await {
-        return Some(slot0);
-    };
-    None
+/// Tick bitmap words `(pool id, word, tick spacing)` of v4 pools read through the
+/// `StateView`, in the same two rounds of batches as `get_v3_words`
+pub async fn get_v4_words<P: Provider + Clone>(
+    batcher: &MulticallBatcher<P>,
+    state_view: Address,
+    words: &[(B256, i16, I24)],
+) -> Vec<Option<TicksBitMap>> {
+    let bitmaps = batcher
+        .call(words.iter().map(|(id, word, _)| {
+            (
+                state_view,
+                StateView::getTickBitmapCall {
+                    poolId: *id,
+                    tick: *word,
+                },
+            )
+        }))
+        .await;
+    let ticks = initialized_ticks(
+        words.iter().map(|(_, word, spacing)| (*word, *spacing)),
+        &bitmaps,
+    );
+
+    // `getTickLiquidity` is `getTickInfo` without the fee growth, which the
+    // simulator doesn't need
+    let tick_calls: Vec<(Address, StateView::getTickLiquidityCall)> = words
+        .iter()
+        .zip(&ticks)
+        .flat_map(|((id, _, _), tks)| {
+            tks.iter().map(|tick| {
+                (
+                    state_view,
+                    StateView::getTickLiquidityCall {
+                        poolId: *id,
+                        tick: *tick,
+                    },
+                )
+            })
+        })
+        .collect();
+    let infos = batcher
+        .call(tick_calls)
+        .await
+        .into_iter()
+        .map(|res| res.map(|x| (x.liquidityNet, x.liquidityGross)));
+
+    assemble_words(bitmaps, ticks, infos)
 }
 
-pub async fn get_v4_config<P: Provider + Clone>(
+/// Initialized ticks of each `(word, tick spacing)`, none for the words whose bitmap
+/// could not be read
+fn initialized_ticks(
+    words: impl Iterator<Item = (i16, I24)>,
+    bitmaps: &[Option<U256>],
+) -> Vec<Vec<I24>> {
+    words
+        .zip(bitmaps)
+        .map(|((word, spacing), bitmap)| match bitmap {
+            Some(bitmap) => bitmap_math::extract_ticks_from_bitmap(*bitmap, word, spacing),
+            None => Vec::new(),
+        })
+        .collect()
+}
+
+/// Puts each bitmap back together with the `(liquidity net, liquidity gross)` read for
+/// its ticks, `liquidity` holding the ticks of every word in order
+fn assemble_words(
+    bitmaps: Vec<Option<U256>>,
+    ticks: Vec<Vec<I24>>,
+    mut liquidity: impl Iterator<Item = Option<(i128, u128)>>,
+) -> Vec<Option<TicksBitMap>> {
+    bitmaps
+        .into_iter()
+        .zip(ticks)
+        .map(|(bitmap, tks)| {
+            let mut new_ticks_map = BTreeMap::<I24, TickData>::new();
+            for tick in tks {
+                let res = liquidity.next().flatten();
+                new_ticks_map.insert(
+                    tick,
+                    TickData {
+                        liquidity_net: res.map(|(net, _)| net),
+                        liquidity_gross: res.map(|(_, gross)| gross),
+                    },
+                );
+            }
+            Some(TicksBitMap {
+                bitmap: bitmap?,
+                ticks: new_ticks_map,
+            })
+        })
+        .collect()
+}
+
+pub async fn get_v4_slot0<P: Provider + Clone>(
     pool: StateViewInstance<P>,
     id: B256,
+    block: BlockId,
 ) -> Option<getSlot0Return> {
-    if let Ok(slot0) = pool.getSlot0(id).call().await {
+    if let Ok(slot0) = pool.getSlot0(id).block(block).call().await {
         return Some(slot0);
     };
     None
@@ -130,8 +357,9 @@ pub async fn get_v4_config<P: Provider + Clone>(
 pub async fn get_v4_liquidity<P: Provider + Clone>(
     pool: StateViewInstance<P>,
     id: B256,
+    block: BlockId,
 ) -> Option<u128> {
-    if let Ok(liq) = pool.getLiquidity(id).call().await {
+    if let Ok(liq) = pool.getLiquidity(id).block(block).call().await {
         return Some(liq);
     };
     None
@@ -140,12 +368,40 @@ pub async fn get_v4_liquidity<P: Provider + Clone>(
 pub async fn get_v4_key<P: Provider + Clone>(
     id: B256,
     ps: IPositionManagerInstance<P>,
+    block: BlockId,
 ) -> Result<PoolKey, alloy::contract::Error> {
     let compacted_id: [u8; 25] = id[0..25]
         .try_into()
         .map_err(|_| alloy::contract::Error::NotADeploymentTransaction)?;
 
     ps.poolKeys(alloy::primitives::FixedBytes(compacted_id))
+        .block(block)
         .call()
         .await
 }
+
+/// `PoolKey` of `id` out of the `Initialize` log the pool manager emitted when the pool
+/// was created, for pools the position manager never minted. `Ok(None)` when no pool
+/// was initialized with that id
+pub async fn get_v4_initialize_key<P: Provider + Clone>(
+    pool_manager: &IPoolManagerInstance<P>,
+    id: B256,
+) -> TransportResult<Option<PoolKey>> {
+    let filter = Filter::new()
+        .address(*pool_manager.address())
+        .event_signature(IPoolManager::Initialize::SIGNATURE_HASH)
+        .topic1(id)
+        .from_block(0);
+    let logs = pool_manager.provider().get_logs(&filter).await?;
+
+    Ok(logs
+        .iter()
+        .find_map(|log| IPoolManager::Initialize::decode_log(&log.inner).ok())
+        .map(|log| PoolKey {
+            currency0: log.currency0,
+            currency1: log.currency1,
+            fee: log.fee,
+            tickSpacing: log.tickSpacing,
+            hooks: log.hooks,
+        }))
+}
@@ -0,0 +1,254 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+
+use all_sol_types::sol_types::IUniswapV2Factory::IUniswapV2FactoryInstance;
+use all_sol_types::sol_types::IUniswapV3Factory::PoolCreated;
+use alloy::{
+    eips::BlockId,
+    primitives::Address,
+    providers::Provider,
+    rpc::types::{Filter, Log},
+    sol_types::SolEvent,
+};
+use chains_json::{chain_json_model::DexJsonModel, chains::ChainsJsonInput};
+use cortex::{cortex::WsProvider, generate_fallback_provider};
+use shape::{d_any::DexId, id_address::IdAddress, p_config::V3Config};
+
+use crate::{calls::get_v2_pairs, master_context::MasterContext, ws_supervisor::Backoff};
+
+/// Times an `allPairs` page is requested before its pairs are given up on
+const PAGE_ATTEMPTS: u32 = 5;
+
+/// Factory of a configured dex
+#[derive(Debug, Clone, Copy)]
+enum Factory {
+    V2(Address),
+    V3(Address),
+}
+
+/// Registers every pool the configured v2/v3 factories ever created.
+///
+/// Runs once at startup through the http nodes of each chain, pools created afterwards
+/// come in through the `PairCreated`/`PoolCreated` logs of the chain subscriptions.
+pub struct Discovery {
+    providers: HashMap<u64, WsProvider>,
+    factories: HashMap<u64, Vec<Factory>>,
+    ctx: Arc<MasterContext>,
+    /// `allPairs` calls per multicall
+    pub page_size: u64,
+    /// max blocks per `PoolCreated` `eth_getLogs` request, halved while the node
+    /// refuses the range
+    pub log_chunk: u64,
+    /// block the `PoolCreated` scans start at when the deployment block of a factory
+    /// can't be found
+    pub from_block: u64,
+}
+
+impl Discovery {
+    pub fn new(chains: &ChainsJsonInput, ctx: Arc<MasterContext>) -> Self {
+        let mut providers = HashMap::new();
+        let mut factories = HashMap::<u64, Vec<Factory>>::new();
+        for (chain_id, chain) in chains.chains.iter() {
+            if let Some(provider) = generate_fallback_provider(chain.http_nodes_urls.clone()) {
+                providers.insert(*chain_id, provider);
+            }
+
+            for dex in chain.dexes.iter() {
+                let factory = match dex {
+                    DexJsonModel::V2 { address, .. } => Address::from_str(address).map(Factory::V2),
+                    DexJsonModel::V3 { address, .. } => Address::from_str(address).map(Factory::V3),
+                    _ => continue,
+                };
+                match factory {
+                    Ok(f) => factories.entry(*chain_id).or_default().push(f),
+                    Err(err) => {
+                        println!("invalid factory address on chain {}: {:?}", chain_id, err)
+                    }
+                }
+            }
+        }
+
+        Self {
+            providers,
+            factories,
+            ctx,
+            page_size: 500,
+            log_chunk: 10_000,
+            from_block: 0,
+        }
+    }
+
+    pub async fn run(self) {
+        for (chain_id, factories) in self.factories.iter() {
+            let Some(provider) = self.providers.get(chain_id) else {
+                println!("no http node to discover pools of chain {}", chain_id);
+                continue;
+            };
+
+            for factory in factories {
+                let found = match factory {
+                    Factory::V2(address) => self.enumerate_v2(*chain_id, provider, *address).await,
+                    Factory::V3(address) => self.enumerate_v3(*chain_id, provider, *address).await,
+                };
+                println!(
+                    "chain {} factory {:?} discovered {} pools",
+                    chain_id, factory, found
+                );
+            }
+        }
+    }
+
+    /// Walks `allPairs` page by page, the v2 fetcher fills config and reserves
+    async fn enumerate_v2(&self, chain_id: u64, provider: &WsProvider, address: Address) -> u64 {
+        let factory = IUniswapV2FactoryInstance::new(address, provider.clone());
+        let Ok(len) = factory.allPairsLength().call().await else {
+            println!(
+                "chain {} factory {} allPairsLength failed",
+                chain_id, address
+            );
+            return 0;
+        };
+        let len = len.saturating_to::<u64>();
+        let dex = DexId::from_factory(chain_id, address);
+
+        let page = self.page_size.max(1);
+        let mut pages: VecDeque<(u64, u64, u32)> = (0..len)
+            .step_by(page as usize)
+            .map(|from| (from, (from + page).min(len), 0))
+            .collect();
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(16));
+
+        let mut found = 0;
+        // a failed page goes back behind the others, so one bad page doesn't hold them up
+        while let Some((from, to, attempts)) = pages.pop_front() {
+            let Some(pairs) = get_v2_pairs(factory.clone(), from, to, BlockId::latest()).await
+            else {
+                if attempts + 1 >= PAGE_ATTEMPTS {
+                    println!(
+                        "chain {} factory {} pairs {}..{} failed {} times, skipping them",
+                        chain_id, address, from, to, PAGE_ATTEMPTS
+                    );
+                    continue;
+                }
+                let delay = backoff.next_delay();
+                println!(
+                    "chain {} factory {} pairs {}..{} failed, retrying in {:?}",
+                    chain_id, address, from, to, delay
+                );
+                tokio::time::sleep(delay).await;
+                pages.push_back((from, to, attempts + 1));
+                continue;
+            };
+            backoff.reset();
+
+            for pair in pairs {
+                let key = IdAddress {
+                    id: chain_id,
+                    address: pair,
+                };
+                self.ctx.register_v2_pool(key, dex, None);
+                found += 1;
+            }
+        }
+        found
+    }
+
+    /// Scans the `PoolCreated` logs of the factory, which carry the whole pool config
+    async fn enumerate_v3(&self, chain_id: u64, provider: &WsProvider, address: Address) -> u64 {
+        let Ok(head) = provider.get_block_number().await else {
+            println!("chain {} head lookup failed", chain_id);
+            return 0;
+        };
+        let dex = DexId::from_factory(chain_id, address);
+        let filter = Filter::new()
+            .address(address)
+            .event_signature(PoolCreated::SIGNATURE_HASH);
+
+        let mut found = 0;
+        let mut from = self
+            .deployment_block(chain_id, provider, address, head)
+            .await;
+        let mut span = self.log_chunk.max(1);
+        while from <= head {
+            let to = from.saturating_add(span - 1).min(head);
+            match provider
+                .get_logs(&filter.clone().from_block(from).to_block(to))
+                .await
+            {
+                Ok(logs) => {
+                    for log in logs.iter() {
+                        let Ok(created) = log.log_decode::<PoolCreated>() else {
+                            continue;
+                        };
+                        self.register_v3(chain_id, dex, created);
+                        found += 1;
+                    }
+                    from = to + 1;
+                    span = self.log_chunk.max(1);
+                }
+                Err(err) if span > 1 => {
+                    println!(
+                        "chain {} PoolCreated scan {}..={} failed, splitting range: {:?}",
+                        chain_id, from, to, err
+                    );
+                    span /= 2;
+                }
+                Err(err) => {
+                    println!(
+                        "chain {} PoolCreated scan of block {} failed, skipping it: {:?}",
+                        chain_id, from, err
+                    );
+                    from = to + 1;
+                }
+            }
+        }
+        found
+    }
+
+    /// First block `factory` has code at, found by bisecting `eth_getCode` between
+    /// `from_block` and `head`. Falls back to `from_block` when the node can't serve the
+    /// state of old blocks
+    async fn deployment_block(
+        &self,
+        chain_id: u64,
+        provider: &WsProvider,
+        factory: Address,
+        head: u64,
+    ) -> u64 {
+        let (mut low, mut high) = (self.from_block, head);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            match provider.get_code_at(factory).number(mid).await {
+                Ok(code) if code.is_empty() => low = mid + 1,
+                Ok(_) => high = mid,
+                Err(err) => {
+                    println!(
+                        "chain {} factory {} deployment lookup failed, scanning from block {}: {:?}",
+                        chain_id, factory, self.from_block, err
+                    );
+                    return self.from_block;
+                }
+            }
+        }
+        low
+    }
+
+    fn register_v3(&self, chain_id: u64, dex: DexId, log: Log<PoolCreated>) {
+        let config = V3Config {
+            name: "".to_string(),
+            fee: log.inner.fee,
+            tick_spacing: log.inner.tickSpacing,
+            token0: log.inner.token0,
+            token1: log.inner.token1,
+        };
+        let key = IdAddress {
+            id: chain_id,
+            address: log.inner.pool,
+        };
+        self.ctx.register_v3_pool(key, dex, config);
+    }
+}
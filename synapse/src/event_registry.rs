@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+
+use alloy::{primitives::B256, rpc::types::Log};
+use dashmap::DashMap;
+
+/// A set of events decoded from raw logs into one response enum.
+///
+/// Implemented through `event_registry!`, registering a `SolEvent` type there is all it
+/// takes for its logs to be decoded.
+pub trait EventRegistry: Clone + 'static {
+    type Response;
+
+    /// signature, topic0 and kind of every registered event
+    const EVENTS: &'static [(&'static str, B256, Self)];
+
+    fn decode(&self, log: &Log) -> Result<Self::Response, alloy::sol_types::Error>;
+
+    /// Signatures to subscribe to, in the form `Filter::events` takes
+    fn signatures() -> Vec<&'static str> {
+        Self::EVENTS
+            .iter()
+            .map(|(signature, _, _)| *signature)
+            .collect()
+    }
+
+    /// Kinds registered under each topic0, events with the same signature but a
+    /// different indexed layout (erc20 and erc721 `Transfer`) share one
+    fn events_map() -> HashMap<B256, Vec<Self>> {
+        let mut map = HashMap::<B256, Vec<Self>>::new();
+        for (_, topic0, kind) in Self::EVENTS {
+            map.entry(*topic0).or_default().push(kind.clone());
+        }
+        map
+    }
+}
+
+/// Declares a kind enum, its response enum and their `EventRegistry` impl out of
+/// `event type => variant` pairs
+macro_rules! event_registry {
+    (
+        $(#[$meta:meta])*
+        $vis:vis enum $kind:ident => $response:ident {
+            $($event:ty => $variant:ident),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, PartialEq, Eq)]
+        $vis enum $kind {
+            $($variant()),*
+        }
+
+        #[derive(Debug, Clone)]
+        $vis enum $response {
+            $($variant(alloy::rpc::types::Log<$event>)),*
+        }
+
+        impl $crate::event_registry::EventRegistry for $kind {
+            type Response = $response;
+
+            const EVENTS: &'static [(&'static str, alloy::primitives::B256, Self)] = &[
+                $((
+                    <$event as alloy::sol_types::SolEvent>::SIGNATURE,
+                    <$event as alloy::sol_types::SolEvent>::SIGNATURE_HASH,
+                    $kind::$variant(),
+                )),*
+            ];
+
+            fn decode(
+                &self,
+                log: &alloy::rpc::types::Log,
+            ) -> Result<$response, alloy::sol_types::Error> {
+                match self {
+                    $($kind::$variant() => log.log_decode::<$event>().map($response::$variant)),*
+                }
+            }
+        }
+    };
+}
+pub(crate) use event_registry;
+
+/// Decodes logs of a registry, counting the logs whose topic0 is registered but whose
+/// data doesn't decode into any of the events behind it
+pub struct EventDecoder<E: EventRegistry> {
+    map: HashMap<B256, Vec<E>>,
+    failures: DashMap<B256, u64>,
+}
+
+impl<E: EventRegistry> Default for EventDecoder<E> {
+    fn default() -> Self {
+        Self {
+            map: E::events_map(),
+            failures: DashMap::new(),
+        }
+    }
+}
+
+impl<E: EventRegistry> EventDecoder<E> {
+    /// First kind registered for the topic0 of the log
+    pub fn kind(&self, log: &Log) -> Option<&E> {
+        self.map.get(log.topic0()?)?.first()
+    }
+
+    /// `None` for logs of unregistered events and for failures, which are counted
+    pub fn decode(&self, log: &Log) -> Option<E::Response> {
+        match self.try_decode(log)? {
+            Ok(response) => Some(response),
+            Err(topic0) => {
+                *self.failures.entry(topic0).or_default() += 1;
+                None
+            }
+        }
+    }
+
+    /// Like `decode`, but a log `other` decodes is another event sharing the topic0
+    /// (an erc721 `Transfer` seen by the v2 pair `Transfer`) and isn't a failure
+    pub fn decode_or_defer<O: EventRegistry>(
+        &self,
+        log: &Log,
+        other: &EventDecoder<O>,
+    ) -> Option<E::Response> {
+        match self.try_decode(log)? {
+            Ok(response) => Some(response),
+            Err(_) if other.try_decode(log).is_some_and(|r| r.is_ok()) => None,
+            Err(topic0) => {
+                *self.failures.entry(topic0).or_default() += 1;
+                None
+            }
+        }
+    }
+
+    /// `None` for logs of unregistered events, the topic0 of the log when none of the
+    /// events behind it decode it
+    fn try_decode(&self, log: &Log) -> Option<Result<E::Response, B256>> {
+        let topic0 = log.topic0()?;
+        let kinds = self.map.get(topic0)?;
+
+        let decoded = kinds.iter().find_map(|kind| kind.decode(log).ok());
+        Some(decoded.ok_or(*topic0))
+    }
+
+    /// Failures so far per topic0
+    pub fn failures(&self) -> Vec<(B256, u64)> {
+        self.failures
+            .iter()
+            .map(|e| (*e.key(), *e.value()))
+            .collect()
+    }
+}
@@ -0,0 +1,240 @@
+use alloy::{primitives::B256, rpc::types::Log};
+use dashmap::DashMap;
+use shape::{
+    p_key::AnyPoolKey,
+    p_state::{SyncPoint, V2State, V3State},
+    p_ticks::PoolWords,
+};
+
+/// Blocks a log needs on top of it before the state it produced is considered final
+pub const DEFAULT_CONFIRMATIONS: u64 = 12;
+
+/// Pool state right before a log was applied
+#[derive(Debug, Clone)]
+pub enum PoolSnapshot {
+    V2(Option<V2State>),
+    V3(Option<V3State>),
+    V4(Option<V3State>),
+    /// a position change also touches the tick words
+    V3Position(Option<V3State>, Option<PoolWords>),
+    V4Position(Option<V3State>, Option<PoolWords>),
+}
+
+#[derive(Debug, Clone)]
+pub struct JournalEntry {
+    pub block_number: u64,
+    pub block_hash: Option<B256>,
+    pub log_index: Option<u64>,
+    pub pool: AnyPoolKey,
+    pub before: PoolSnapshot,
+    /// point the pool was synced at before the log, restored with the snapshot so the
+    /// replayed logs of the new branch are applied again
+    pub synced_before: Option<SyncPoint>,
+}
+
+impl JournalEntry {
+    pub fn new<T>(
+        log: &Log<T>,
+        pool: AnyPoolKey,
+        before: PoolSnapshot,
+        synced_before: Option<SyncPoint>,
+    ) -> Option<Self> {
+        Some(Self {
+            block_number: log.block_number?,
+            block_hash: log.block_hash,
+            log_index: log.log_index,
+            pool,
+            before,
+            synced_before,
+        })
+    }
+}
+
+/// Per chain journal of the pool state deltas that are not final yet
+pub struct StateJournal {
+    confirmations: u64,
+    entries: DashMap<u64, Vec<JournalEntry>>,
+    heads: DashMap<u64, u64>,
+}
+
+impl StateJournal {
+    pub fn new(confirmations: u64) -> Self {
+        Self {
+            confirmations,
+            entries: DashMap::new(),
+            heads: DashMap::new(),
+        }
+    }
+
+    /// Highest block of the chain that can no longer be reverted by the journal
+    pub fn finalized_block(&self, chain_id: u64) -> Option<u64> {
+        self.heads
+            .get(&chain_id)
+            .and_then(|head| head.checked_sub(self.confirmations))
+    }
+
+    /// Journals `entry`, the entries that became final are dropped when it moves the
+    /// head of the chain forward
+    pub fn record(&self, chain_id: u64, entry: JournalEntry) {
+        let advanced = {
+            let mut head = self.heads.entry(chain_id).or_insert(0);
+            let advanced = entry.block_number > *head;
+            *head = (*head).max(entry.block_number);
+            advanced
+        };
+
+        let mut entries = self.entries.entry(chain_id).or_default();
+        entries.push(entry);
+        let finalized = self.finalized_block(chain_id).filter(|_| advanced);
+        if let Some(finalized) = finalized {
+            entries.retain(|e| e.block_number > finalized);
+        }
+    }
+
+    /// Removes every entry from `block_number` onwards, newest first, so restoring their
+    /// snapshots in order brings the pools back to the state before that block.
+    ///
+    /// A removed log is only reverted when its `block_hash` is the one the entries of
+    /// its block were recorded with, or either hash is unknown. Entries of that block all
+    /// from another hash mean the pools already follow another fork, nothing is removed.
+    ///
+    /// Returns `None` when the block is already final and can't be reverted.
+    pub fn rollback(
+        &self,
+        chain_id: u64,
+        block_number: u64,
+        block_hash: Option<B256>,
+    ) -> Option<Vec<JournalEntry>> {
+        if self
+            .finalized_block(chain_id)
+            .is_some_and(|finalized| block_number <= finalized)
+        {
+            return None;
+        }
+
+        let mut reverted = Vec::new();
+        if let Some(mut entries) = self.entries.get_mut(&chain_id) {
+            let other_fork = block_hash.is_some_and(|hash| {
+                let mut at_block = entries
+                    .iter()
+                    .filter(|e| e.block_number == block_number)
+                    .peekable();
+                at_block.peek().is_some()
+                    && at_block.all(|e| e.block_hash.is_some_and(|h| h != hash))
+            });
+            if other_fork {
+                return Some(reverted);
+            }
+
+            let (keep, revert): (Vec<_>, Vec<_>) = entries
+                .drain(..)
+                .partition(|e| e.block_number < block_number);
+            *entries = keep;
+            reverted = revert;
+            reverted.reverse();
+        }
+
+        Some(reverted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::Address;
+    use shape::id_address::IdAddress;
+
+    use super::*;
+
+    fn entry(block_number: u64, log_index: u64) -> JournalEntry {
+        hashed_entry(block_number, log_index, None)
+    }
+
+    fn hashed_entry(block_number: u64, log_index: u64, block_hash: Option<B256>) -> JournalEntry {
+        JournalEntry {
+            block_number,
+            block_hash,
+            log_index: Some(log_index),
+            pool: AnyPoolKey::V2(IdAddress {
+                id: 1,
+                address: Address::repeat_byte(1),
+            }),
+            before: PoolSnapshot::V2(None),
+            synced_before: None,
+        }
+    }
+
+    fn positions(entries: &[JournalEntry]) -> Vec<(u64, Option<u64>)> {
+        entries
+            .iter()
+            .map(|e| (e.block_number, e.log_index))
+            .collect()
+    }
+
+    #[test]
+    fn rollback_returns_entries_newest_first() {
+        let journal = StateJournal::new(10);
+        for (block, index) in [(100, 0), (100, 1), (101, 0), (102, 0), (102, 3)] {
+            journal.record(1, entry(block, index));
+        }
+
+        let reverted = journal.rollback(1, 101, None).unwrap();
+        assert_eq!(
+            positions(&reverted),
+            vec![(102, Some(3)), (102, Some(0)), (101, Some(0))]
+        );
+
+        // what is left is what came before the reverted block
+        let reverted = journal.rollback(1, 100, None).unwrap();
+        assert_eq!(positions(&reverted), vec![(100, Some(1)), (100, Some(0))]);
+        assert!(journal.rollback(1, 100, None).unwrap().is_empty());
+        assert!(journal.rollback(2, 100, None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rollback_refuses_final_blocks() {
+        let journal = StateJournal::new(10);
+        journal.record(1, entry(100, 0));
+        journal.record(1, entry(110, 0));
+        assert_eq!(journal.finalized_block(1), Some(100));
+
+        assert!(journal.rollback(1, 100, None).is_none());
+        assert!(journal.rollback(1, 95, None).is_none());
+        let reverted = journal.rollback(1, 101, None).unwrap();
+        assert_eq!(positions(&reverted), vec![(110, Some(0))]);
+    }
+
+    #[test]
+    fn final_entries_are_pruned_when_the_head_advances() {
+        let journal = StateJournal::new(10);
+        journal.record(1, entry(100, 0));
+        journal.record(1, entry(105, 0));
+        // a late log of an older block doesn't move the head
+        journal.record(1, entry(95, 0));
+        assert_eq!(journal.entries.get(&1).unwrap().len(), 3);
+
+        journal.record(1, entry(111, 0));
+        assert_eq!(journal.finalized_block(1), Some(101));
+        assert_eq!(
+            positions(&journal.entries.get(&1).unwrap()),
+            vec![(105, Some(0)), (111, Some(0))]
+        );
+    }
+
+    #[test]
+    fn removed_logs_of_another_fork_revert_nothing() {
+        let (old, new) = (B256::repeat_byte(1), B256::repeat_byte(2));
+        let journal = StateJournal::new(10);
+        journal.record(1, hashed_entry(100, 0, Some(new)));
+        journal.record(1, hashed_entry(101, 0, None));
+
+        // the pools follow `new` at block 100, a late removal of `old` leaves them there
+        assert!(journal.rollback(1, 100, Some(old)).unwrap().is_empty());
+        assert_eq!(journal.entries.get(&1).unwrap().len(), 2);
+        // an entry without a hash is reverted whatever the removed log's hash
+        let reverted = journal.rollback(1, 101, Some(old)).unwrap();
+        assert_eq!(positions(&reverted), vec![(101, Some(0))]);
+
+        let reverted = journal.rollback(1, 100, Some(new)).unwrap();
+        assert_eq!(positions(&reverted), vec![(100, Some(0))]);
+    }
+}
@@ -1,34 +1,59 @@
-use std::{collections::HashMap, str::FromStr, time::Duration};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
-use all_sol_types::sol_types::{IPoolManager, IUniswapV2Pair, StateView, V3Pool};
 use alloy::{
-    hex::HEX_DECODE_LUT,
     primitives::B256,
     providers::{Provider, ProviderBuilder, WsConnect},
-    rpc::{client::RpcClient, types::Filter},
+    pubsub::Subscription,
+    rpc::{
+        client::RpcClient,
+        types::{Filter, Log},
+    },
     signers::k256::U256,
-    sol_types::SolEvent,
     transports::{RpcError, TransportErrorKind, http::reqwest::Url},
 };
-use chains_json::{chain::ChainJsonInput, chains::ChainsJsonInput};
-//use chains_json::chains::ChainsJsonInput;
-use dashmap::DashMap;
-use shape::id_address::IdAddress;
+use chains_json::chains::ChainsJsonInput;
+use tokio::task::JoinSet;
 
 use crate::{
+    backfill::Backfiller,
+    discovery::Discovery,
+    journal::DEFAULT_CONFIRMATIONS,
     master_context::MasterContext,
-    pool_event::{
-        UnifiedPoolEvent, UnifiedPoolEventResponse, generate_pool_events, generate_pools_events_map,
-    },
+    pool_event::generate_pool_events,
+    tick_window::{TickWindow, WindowSize},
+    token_event::generate_token_events,
+    v2_fetcher::V2Fetcher,
+    v3_fetcher::V3Fetcher,
+    ws_supervisor::{GapRequest, PendingGap, SupervisorConfig, supervise_chain},
 };
 
+mod backfill;
 mod calls;
+mod discovery;
+mod event_registry;
+mod journal;
 mod master_context;
 mod pool_event;
+mod provenance;
+mod tick_window;
 mod token_event;
 mod v2_fetcher;
 mod v3_fetcher;
 mod v4_fetcher;
+mod ws_supervisor;
+
+/// Words next to the current tick grow the window, up to 4 words per side, until 1e21
+/// liquidity can be crossed on each side
+const TICK_WINDOW: TickWindow = TickWindow {
+    size: WindowSize::Depth {
+        target: 10u128.pow(21),
+        max_words: 4,
+    },
+    max_extensions: 8,
+};
+
+/// How often new log decode failures are reported
+const DECODE_REPORT_INTERVAL: Duration = Duration::from_secs(60);
 
 pub type WsProvider = alloy::providers::fillers::FillProvider<
     alloy::providers::fillers::JoinFill<
@@ -47,11 +72,18 @@ pub type WsProvider = alloy::providers::fillers::FillProvider<
     alloy::providers::RootProvider,
 >;
 
-fn main() {
-    let data = chains_json::chains::ChainsJsonInput::try_default() else {
+#[tokio::main]
+async fn main() {
+    let Ok(data) = chains_json::chains::ChainsJsonInput::try_default() else {
         panic!("invalid json data")
     };
-    let ctx = todo!();
+
+    watch_chains(data).await;
+}
+
+/// Signatures of every pool and token event the listeners subscribe to
+pub fn watched_events() -> Vec<&'static str> {
+    [generate_pool_events(), generate_token_events()].concat()
 }
 
 pub async fn ws_sub<P: Provider + Clone>(
@@ -76,33 +108,89 @@ pub async fn ws_provider(url: Url) -> Result<WsProvider, RpcError<TransportError
     }
 }
 
-async fn watch_chains() {
-    let waiting_time = 2;
-    let listening_time = 2;
+async fn watch_chains(chains: ChainsJsonInput) {
+    let ctx = Arc::new(MasterContext::new(DEFAULT_CONFIRMATIONS, TICK_WINDOW));
+    let mut listeners = JoinSet::new();
+    let (gaps_tx, gaps_rx) = futures::channel::mpsc::unbounded::<GapRequest>();
+    let backfiller = Backfiller::new(&chains, ctx.clone(), 20, gaps_tx.clone());
+    let discovery = Discovery::new(&chains, ctx.clone());
 
-    let Ok(chains) = ChainsJsonInput::try_default() else {
-        panic!("invalid json data")
-    };
-    let pool_events: HashMap<B256, UnifiedPoolEvent> = generate_pools_events_map();
+    for (chain_id, chain) in chains.chains {
+        if chain.ws_nodes_urls.is_empty() {
+            println!("no ws node configured for chain {}, skipping", chain_id);
+            continue;
+        }
 
-    let available_chains = chains.chains;
+        listeners.spawn(supervise_chain(
+            chain_id,
+            chain.ws_nodes_urls,
+            chain.dexes,
+            ctx.clone(),
+            gaps_tx.clone(),
+            SupervisorConfig::default(),
+        ));
+    }
 
-    let master_context = for (idx, x) in available_chains.iter() {
-        for url_str in x.ws_nodes_urls.iter() {
-            let url = Url::from_str(url_str).unwrap();
-            let ws_provider = match ws_provider(url).await {
-                Ok(ok) => ok,
-                Err(err) => {
-                    panic!("ws provider creation failed: {:?}", err)
-                }
-            };
-            MasterContext
-        }
+    if listeners.is_empty() {
+        println!("no chain could be watched");
+        return;
+    }
+
+    listeners.spawn(backfiller.run(gaps_rx));
+    listeners.spawn(discovery.run());
+    listeners.spawn(V2Fetcher::default().run(ctx.clone()));
+    let v3_fetcher = V3Fetcher {
+        window: TICK_WINDOW,
+        ..Default::default()
     };
+    listeners.spawn(v3_fetcher.run(ctx.clone()));
+    listeners.spawn(ctx.v4_fetcher().run());
+    listeners.spawn(report_decode_failures(ctx.clone(), DECODE_REPORT_INTERVAL));
 
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => println!("sigint received, stopping listeners"),
+        _ = async { while listeners.join_next().await.is_some() {} } => println!("all listeners finished"),
+    }
+
+    listeners.shutdown().await;
+    for (topic0, failures) in decode_failures(&ctx) {
+        println!("{} logs of topic {} failed to decode", failures, topic0);
+    }
     println!("exiting");
 }
 
+/// Decode failures of pool and token logs per topic0
+fn decode_failures(ctx: &MasterContext) -> HashMap<B256, u64> {
+    let mut failures = HashMap::new();
+    for (topic0, count) in ctx
+        .pool_decode_failures()
+        .into_iter()
+        .chain(ctx.tokens().decode_failures())
+    {
+        *failures.entry(topic0).or_insert(0) += count;
+    }
+    failures
+}
+
+/// Prints every `interval` the topics whose logs failed to decode since the last report
+async fn report_decode_failures(ctx: Arc<MasterContext>, interval: Duration) {
+    let mut reported = HashMap::<B256, u64>::new();
+    loop {
+        tokio::time::sleep(interval).await;
+        for (topic0, failures) in decode_failures(&ctx) {
+            let before = reported.insert(topic0, failures).unwrap_or(0);
+            if failures > before {
+                println!(
+                    "{} more logs of topic {} failed to decode, {} in total",
+                    failures - before,
+                    topic0,
+                    failures
+                );
+            }
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct ChainState<P: Provider> {
     providers: P,
@@ -112,88 +200,87 @@ pub struct ChainState<P: Provider> {
     liquidity: U256,
 }
 
-pub async fn decode_logs_listener_blocking<P: Provider + Clone>(
+/// How a live subscription ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ListenerEnd {
+    /// logs received before it ended
+    pub logs: u64,
+    /// nothing arrived for the idle timeout, the node itself didn't close it
+    pub idle: bool,
+}
+
+/// Processes the logs of a live subscription until it drops, marking every block seen
+/// on `ctx` so the caller can report what was missed while reconnecting.
+///
+/// While `pending` holds a gap that is still being backfilled the logs are buffered,
+/// once it is done they are applied after it, minus the blocks the backfill covered,
+/// so the chain state is always built in block order
+pub async fn decode_logs_listener_blocking(
     chain_id: u64,
-    provider: P,
+    ws: &mut Subscription<Log>,
     ctx: &MasterContext,
-) {
-    let map = generate_pools_events_map();
-    let filter = Filter::new().events(generate_pool_events());
-    if let Ok(mut ws) = provider.subscribe_logs(&filter).await {
-        while let Ok(log) = ws.recv().await {
-            if let Some(w) = log.topic0() {
-                if let Some(res) = map.get(w) {
-                    let response = match res {
-                        // Uniswap V2
-                        UnifiedPoolEvent::V2Mint() => log
-                            .log_decode::<all_sol_types::sol_types::IUniswapV2Pair::Mint>()
-                            .ok()
-                            .map(UnifiedPoolEventResponse::V2Mint),
-                        UnifiedPoolEvent::V2Burn() => log
-                            .log_decode::<all_sol_types::sol_types::IUniswapV2Pair::Burn>()
-                            .ok()
-                            .map(UnifiedPoolEventResponse::V2Burn),
-                        UnifiedPoolEvent::V2Swap() => log
-                            .log_decode::<all_sol_types::sol_types::IUniswapV2Pair::Swap>()
-                            .ok()
-                            .map(UnifiedPoolEventResponse::V2Swap),
-                        UnifiedPoolEvent::V2Sync() => log
-                            .log_decode::<IUniswapV2Pair::Sync>()
-                            .ok()
-                            .map(UnifiedPoolEventResponse::V2Sync),
-                        UnifiedPoolEvent::V2Approval() => log
-                            .log_decode::<IUniswapV2Pair::Approval>()
-                            .ok()
-                            .map(UnifiedPoolEventResponse::V2Approval),
-                        UnifiedPoolEvent::V2Transfer() => log
-                            .log_decode::<IUniswapV2Pair::Transfer>()
-                            .ok()
-                            .map(UnifiedPoolEventResponse::V2Transfer),
-                        // Uniswap V3
-                        UnifiedPoolEvent::V3Mint() => log
-                            .log_decode::<V3Pool::Mint>()
-                            .ok()
-                            .map(UnifiedPoolEventResponse::V3Mint),
-                        UnifiedPoolEvent::V3Swap() => log
-                            .log_decode::<V3Pool::Swap>()
-                            .ok()
-                            .map(UnifiedPoolEventResponse::V3Swap),
-                        UnifiedPoolEvent::V3Collect() => log
-                            .log_decode::<V3Pool::Collect>()
-                            .ok()
-                            .map(UnifiedPoolEventResponse::V3Collect),
-                        UnifiedPoolEvent::V3Burn() => log
-                            .log_decode::<V3Pool::Burn>()
-                            .ok()
-                            .map(UnifiedPoolEventResponse::V3Burn),
-                        UnifiedPoolEvent::V3Flash() => log
-                            .log_decode::<V3Pool::Flash>()
-                            .ok()
-                            .map(UnifiedPoolEventResponse::V3Flash),
-                        // Uniswap V4 (PoolManager Events)
-                        UnifiedPoolEvent::V4Swap() => log
-                            .log_decode::<IPoolManager::Swap>()
-                            .ok()
-                            .map(UnifiedPoolEventResponse::V4Swap),
-                        UnifiedPoolEvent::V4Modify() => log
-                            .log_decode::<IPoolManager::ModifyLiquidity>()
-                            .ok()
-                            .map(UnifiedPoolEventResponse::V4Modify),
-                        UnifiedPoolEvent::V4Donate() => log
-                            .log_decode::<IPoolManager::Donate>()
-                            .ok()
-                            .map(UnifiedPoolEventResponse::V4Donate),
-                        UnifiedPoolEvent::V4Initialize() => log
-                            .log_decode::<IPoolManager::Initialize>()
-                            .ok()
-                            .map(UnifiedPoolEventResponse::V4Initialize),
-                    };
-
-                    if let Some(r) = response {
-                        r.handle(ctx, chain_id);
-                    }
+    idle_timeout: Duration,
+    pending: &mut Option<PendingGap>,
+) -> ListenerEnd {
+    let mut logs = 0;
+    let mut buffered: Vec<Log> = Vec::new();
+    loop {
+        let received = match pending.as_mut() {
+            Some(gap) => tokio::select! {
+                _ = &mut gap.done => None,
+                received = tokio::time::timeout(idle_timeout, ws.recv()) => Some(received),
+            },
+            None => Some(tokio::time::timeout(idle_timeout, ws.recv()).await),
+        };
+
+        let Some(received) = received else {
+            let to = pending.take().map(|gap| gap.to);
+            for log in buffered.drain(..) {
+                let backfilled =
+                    !log.removed && log.block_number.zip(to).is_some_and(|(b, to)| b <= to);
+                if !backfilled {
+                    handle_live_log(chain_id, &log, ctx);
                 }
             }
+            continue;
+        };
+
+        let log = match received {
+            Ok(Ok(log)) => log,
+            Ok(Err(err)) => {
+                println!("chain {} subscription closed: {:?}", chain_id, err);
+                return ListenerEnd { logs, idle: false };
+            }
+            Err(_) => {
+                println!(
+                    "chain {} subscription idle for {:?}",
+                    chain_id, idle_timeout
+                );
+                return ListenerEnd { logs, idle: true };
+            }
+        };
+        logs += 1;
+
+        if pending.is_some() {
+            buffered.push(log);
+        } else {
+            handle_live_log(chain_id, &log, ctx);
         }
     }
 }
+
+fn handle_live_log(chain_id: u64, log: &Log, ctx: &MasterContext) {
+    ctx.tokens().handle_log(chain_id, log);
+    if log.removed {
+        ctx.handle_removed_log(chain_id, log);
+        return;
+    }
+
+    if let Some(r) = ctx.decode_pool_log(log) {
+        r.handle(ctx, chain_id);
+    }
+
+    if let Some(block) = log.block_number {
+        ctx.mark_block(chain_id, block);
+    }
+}
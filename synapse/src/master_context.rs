@@ -1,48 +1,79 @@
 use std::{
-    hash::Hash,
-    sync::{Arc, Mutex, RwLock},
+    collections::BTreeSet,
+    str::FromStr,
+    sync::{Arc, RwLock},
 };
 
 use all_sol_types::sol_types::{
-    IPoolManager::{self, IPoolManagerInstance},
-    IPositionManager::IPositionManagerInstance,
-    IUniswapV2Pair, PoolKey,
-    StateView::StateViewInstance,
-    V3Pool,
+    IPoolManager, IUniswapV2Factory, IUniswapV2Pair, IUniswapV3Factory, V3Pool,
 };
 use alloy::{
-    primitives::{B256, U256, map::HashMap},
-    providers::{Provider, RootProvider, fillers::FillProvider},
-    rpc::types::{Log, state},
+    eips::BlockId,
+    primitives::{
+        Address, B256, U256,
+        aliases::{I24, U24},
+    },
+    rpc::types::Log,
 };
-use chains_json::chain_json_model::ChainDataJsonModel;
+use chains_json::chain_json_model::DexJsonModel;
 use cortex::{
-    cortex::{Cortex, WsProvider},
-    types::{AnyPartialPool, PartialV2Pool, PartialV3Pool, PartialV4Pool, PoolEvaluation},
+    cortex::WsProvider,
+    types::{
+        AnyPartialPool, PartialV2Dex, PartialV2Pool, PartialV3Dex, PartialV3Pool, PartialV4Pool,
+        PoolEvaluation,
+    },
 };
-use dashmap::{DashMap, Map};
-use futures::{SinkExt, channel::mpsc::Receiver, executor::block_on, stream::FuturesOrdered};
+use dashmap::DashMap;
 use shape::{
+    d_any::{DexId, PoolProvenance, V2Fees, V3Fees},
     id_address::{IdAddress, IdKey},
-    p_config::{V3Config, V4Config},
+    p_config::{V2Config, V3Config, V4Config},
     p_key::AnyPoolKey,
-    p_state::V3State,
+    p_state::{SyncPoint, V2State, V3State},
+    p_ticks::{PoolWords, TicksBitMap},
+};
+
+use v3::v3_base::{
+    bitmap_math::get_pos_from_tick, err::TradeError, states::TradeOutcome, trade_math,
 };
 
 use crate::{
-    calls::{self, get_v4_key},
-    v4_fetcher::{self, V4Contracts, V4FetchArgs, V4Fetcher},
+    calls::{MulticallBatcher, pinned_block},
+    event_registry::EventDecoder,
+    journal::{JournalEntry, PoolSnapshot, StateJournal},
+    pool_event::{UnifiedPoolEvent, UnifiedPoolEventResponse},
+    tick_window::{TickWindow, V3Words, V4Words},
+    token_event::TokenTracker,
+    v3_fetcher::to_trade_state,
+    v4_fetcher::{V4Contracts, V4FetchArgs, V4Fetcher},
 };
 
+/// Failed fetches in a row after which a queued pool waits for its next log
+const MAX_FETCH_RETRIES: u32 = 5;
+
 pub struct MasterContext {
     chains_providers: DashMap<u64, WsProvider>,
     v2_pools: DashMap<IdAddress, PartialV2Pool>,
     v3_pools: DashMap<IdAddress, PartialV3Pool>,
-    v4_pools: DashMap<IdKey, PartialV4Pool>,
-    v4_fetch_worker: Arc<RwLock<V4Fetcher>>,
+    v4_pools: Arc<DashMap<IdKey, PartialV4Pool>>,
+    v4_fetch_worker: Arc<V4Fetcher>,
     pools_by_token: DashMap<IdAddress, Vec<EvaluatedPool>>,
     v4_contracts: DashMap<u64, V4Contracts<WsProvider>>,
-    v2_reserves_queue: Arc<RwLock<Vec<IdAddress>>>,
+    v2_reserves_queue: Arc<RwLock<BTreeSet<IdAddress>>>,
+    v2_dexes: DashMap<IdAddress, PartialV2Dex>,
+    v3_dexes: DashMap<IdAddress, PartialV3Dex>,
+    v3_hydrate_queue: Arc<RwLock<BTreeSet<IdAddress>>>,
+    fetch_failures: DashMap<AnyPoolKey, u32>,
+    /// position changes of v3 pools that reached ticks in words not loaded yet, replayed
+    /// onto those words when a fetch older than the change brings them
+    skipped_positions: DashMap<IdAddress, Vec<SkippedPosition>>,
+    last_blocks: DashMap<u64, u64>,
+    journal: StateJournal,
+    v2_swap_stats: DashMap<IdAddress, SwapStats>,
+    pool_decoder: EventDecoder<UnifiedPoolEvent>,
+    tokens: TokenTracker,
+    /// bitmap words kept around the current tick of v3 and v4 pools
+    tick_window: TickWindow,
 }
 
 pub struct EvaluatedPool {
@@ -50,190 +81,1306 @@ pub struct EvaluatedPool {
     eval: Option<PoolEvaluation>,
 }
 
-impl MasterContext {
-    async fn new() -> Self {
-        let v4_fetcher_worker = V4Fetcher {
-            contracts: todo!(),
-            pools: todo!(),
-            not_found: todo!(),
-            sender: todo!(),
-        };
+/// Running totals of the swaps seen on a v2 pair
+#[derive(Debug, Default, Clone)]
+pub struct SwapStats {
+    pub swaps: u64,
+    pub amount0_in: U256,
+    pub amount1_in: U256,
+    pub amount0_out: U256,
+    pub amount1_out: U256,
+}
+
+impl SwapStats {
+    pub fn add(&mut self, swap: &IUniswapV2Pair::Swap) {
+        self.swaps += 1;
+        self.amount0_in = self.amount0_in.saturating_add(swap.amount0In);
+        self.amount1_in = self.amount1_in.saturating_add(swap.amount1In);
+        self.amount0_out = self.amount0_out.saturating_add(swap.amount0Out);
+        self.amount1_out = self.amount1_out.saturating_add(swap.amount1Out);
+    }
+}
+
+/// A mint or burn applied at `at` whose tick words weren't all loaded
+#[derive(Debug, Clone)]
+struct SkippedPosition {
+    at: SyncPoint,
+    tick_lower: I24,
+    tick_upper: I24,
+    liquidity_delta: i128,
+}
 
+impl MasterContext {
+    pub fn new(confirmations: u64, tick_window: TickWindow) -> Self {
+        let v4_pools = Arc::new(DashMap::new());
         Self {
             chains_providers: DashMap::new(),
             v2_pools: DashMap::new(),
             v3_pools: DashMap::new(),
-            v4_pools: DashMap::new(),
-            v4_fetch_worker,
+            v4_fetch_worker: Arc::new(V4Fetcher::new(v4_pools.clone(), tick_window)),
+            v4_pools,
             pools_by_token: DashMap::new(),
             v4_contracts: DashMap::new(),
-            v2_reserves_queue: Arc::new(RwLock::new(Vec::new())),
-        }
-    }
-
-    async fn v4_fetch(args: V4FetchArgs, ctx: &MasterContext) {
-        let (id, chain) = (args.id, args.chain);
-        if let Some(v4_contracts) = ctx.v4_contracts.get(&chain) {
-            let pos_man = v4_contracts.value().position_manager.clone();
-            if let Ok(key) = get_v4_key(id, pos_man).await {
-                let ik = IdKey { id: chain, key: id };
-                ctx.v4_pools
-                    .entry(ik)
-                    .and_modify(|x| x.config = Some(key.clone().into()))
-                    .or_insert_with(|| PartialV4Pool {
-                        chain,
-                        state_view: v4_contracts.state_view.address().clone(),
-                        config: Some(key.into()),
-                        state: None,
-                    });
+            v2_reserves_queue: Arc::new(RwLock::new(BTreeSet::new())),
+            v2_dexes: DashMap::new(),
+            v3_dexes: DashMap::new(),
+            v3_hydrate_queue: Arc::new(RwLock::new(BTreeSet::new())),
+            fetch_failures: DashMap::new(),
+            skipped_positions: DashMap::new(),
+            last_blocks: DashMap::new(),
+            journal: StateJournal::new(confirmations),
+            v2_swap_stats: DashMap::new(),
+            pool_decoder: EventDecoder::default(),
+            tokens: TokenTracker::default(),
+            tick_window,
+        }
+    }
+
+    /// Highest block whose pool state can no longer be reverted by a reorg
+    pub fn finalized_block(&self, chain_id: u64) -> Option<u64> {
+        self.journal.finalized_block(chain_id)
+    }
+
+    fn snapshot(&self, key: &AnyPoolKey) -> PoolSnapshot {
+        match key {
+            AnyPoolKey::V2(k) => {
+                PoolSnapshot::V2(self.v2_pools.get(k).and_then(|p| p.state.clone()))
             }
-        };
+            AnyPoolKey::V3(k) => {
+                PoolSnapshot::V3(self.v3_pools.get(k).and_then(|p| p.state.clone()))
+            }
+            AnyPoolKey::V4(chain, id) => {
+                let k = IdKey {
+                    id: *chain,
+                    key: *id,
+                };
+                PoolSnapshot::V4(self.v4_pools.get(&k).and_then(|p| p.state.clone()))
+            }
+        }
     }
 
-    async fn spaw_v4_fetch_worker(ctx: &MasterContext, mut rx: Receiver<V4FetchArgs>) {
-        let worker = std::thread::scope(|_| async move {
-            while let Ok(r) = rx.recv().await {
-                let Some(v4_contracts) = ctx.v4_contracts.get(&r.chain) else {
-                    continue;
+    fn restore(&self, key: &AnyPoolKey, snapshot: PoolSnapshot, synced_at: Option<SyncPoint>) {
+        self.set_synced(key, synced_at);
+        match (key, snapshot) {
+            (AnyPoolKey::V2(k), PoolSnapshot::V2(state)) => {
+                if let Some(mut p) = self.v2_pools.get_mut(k) {
+                    p.state = state;
+                }
+            }
+            (AnyPoolKey::V3(k), PoolSnapshot::V3(state)) => {
+                if let Some(mut p) = self.v3_pools.get_mut(k) {
+                    p.state = state;
+                }
+            }
+            (AnyPoolKey::V4(chain, id), PoolSnapshot::V4(state)) => {
+                let k = IdKey {
+                    id: *chain,
+                    key: *id,
+                };
+                if let Some(mut p) = self.v4_pools.get_mut(&k) {
+                    p.state = state;
+                }
+            }
+            (AnyPoolKey::V3(k), PoolSnapshot::V3Position(state, words)) => {
+                if let Some(mut p) = self.v3_pools.get_mut(k) {
+                    p.state = state;
+                    p.words = words;
+                }
+                // the reverted mints and burns are not replayed onto words fetched later
+                if let Some(mut skipped) = self.skipped_positions.get_mut(k) {
+                    skipped.retain(|p| {
+                        synced_at.is_some_and(|s| !s.precedes_log(p.at.block, p.at.log_index))
+                    });
+                }
+            }
+            (AnyPoolKey::V4(chain, id), PoolSnapshot::V4Position(state, words)) => {
+                let k = IdKey {
+                    id: *chain,
+                    key: *id,
                 };
+                if let Some(mut p) = self.v4_pools.get_mut(&k) {
+                    p.state = state;
+                    p.words = words;
+                }
+            }
+            _ => (),
+        }
+    }
 
-                let state_view = *v4_contracts.state_view.address();
-                let log = r.log.inner;
+    /// Point of the chain the state of `key` reflects
+    fn synced_at(&self, key: &AnyPoolKey) -> Option<SyncPoint> {
+        match key {
+            AnyPoolKey::V2(k) => self.v2_pools.get(k)?.synced_at,
+            AnyPoolKey::V3(k) => self.v3_pools.get(k)?.synced_at,
+            AnyPoolKey::V4(chain, id) => {
+                self.v4_pools
+                    .get(&IdKey {
+                        id: *chain,
+                        key: *id,
+                    })?
+                    .synced_at
+            }
+        }
+    }
 
-                let Some(v4_contracts) = ctx.v4_contracts.get(&r.chain) else {
-                    continue;
+    fn set_synced(&self, key: &AnyPoolKey, synced_at: Option<SyncPoint>) {
+        match key {
+            AnyPoolKey::V2(k) => {
+                if let Some(mut p) = self.v2_pools.get_mut(k) {
+                    p.synced_at = synced_at;
+                }
+            }
+            AnyPoolKey::V3(k) => {
+                if let Some(mut p) = self.v3_pools.get_mut(k) {
+                    p.synced_at = synced_at;
+                }
+            }
+            AnyPoolKey::V4(chain, id) => {
+                let k = IdKey {
+                    id: *chain,
+                    key: *id,
                 };
+                if let Some(mut p) = self.v4_pools.get_mut(&k) {
+                    p.synced_at = synced_at;
+                }
+            }
+        }
+    }
 
-                let Ok(call) = get_v4_key(r.id, v4_contracts.position_manager.clone()).await else {
-                    continue;
-                };
+    /// Whether `log` is newer than the state of `key`, logs of blocks the pool was read
+    /// at or already applied are part of its state
+    fn is_new_log<T>(&self, key: &AnyPoolKey, log: &Log<T>) -> bool {
+        match (self.synced_at(key), log.block_number) {
+            (Some(synced), Some(block)) => synced.precedes_log(block, log.log_index),
+            _ => true,
+        }
+    }
 
-                let config = V4Config {
-                    fee: call.fee,
-                    tick_spacing: call.tickSpacing,
-                    hooks: call.hooks,
-                    token0: call.currency0,
-                    token1: call.currency1,
-                };
+    /// Moves the sync point of `key` to a log just applied to it, pending logs leave it
+    /// where it is
+    fn mark_synced<T>(&self, key: &AnyPoolKey, log: &Log<T>) {
+        if let Some(block) = log.block_number {
+            self.set_synced(
+                key,
+                Some(SyncPoint::at_log(block, log.block_hash, log.log_index)),
+            );
+        }
+    }
 
-                let state = V3State {
-                    tick: log.tick,
-                    x96price: log.sqrtPriceX96,
-                    liquidity: log.liquidity,
-                };
+    /// Journals the current state of `key` so the log about to be applied can be reverted
+    fn journal<T>(&self, chain_id: u64, log: &Log<T>, key: AnyPoolKey) {
+        let before = self.snapshot(&key);
+        let synced_before = self.synced_at(&key);
+        if let Some(entry) = JournalEntry::new(log, key, before, synced_before) {
+            self.journal.record(chain_id, entry);
+        }
+    }
 
-                PartialV4Pool {
-                    chain: r.chain,
-                    state_view,
-                    config: Some(config),
-                    state: Some(state),
+    /// Like `journal`, also keeping the tick words a position change is about to modify
+    fn journal_position<T>(&self, chain_id: u64, log: &Log<T>, key: AnyPoolKey) {
+        let before = match &key {
+            AnyPoolKey::V3(k) => PoolSnapshot::V3Position(
+                self.v3_pools.get(k).and_then(|p| p.state.clone()),
+                self.v3_pools.get(k).and_then(|p| p.words.clone()),
+            ),
+            AnyPoolKey::V4(chain, id) => {
+                let k = IdKey {
+                    id: *chain,
+                    key: *id,
                 };
+                PoolSnapshot::V4Position(
+                    self.v4_pools.get(&k).and_then(|p| p.state.clone()),
+                    self.v4_pools.get(&k).and_then(|p| p.words.clone()),
+                )
             }
-        })
-        .await;
+            AnyPoolKey::V2(_) => self.snapshot(&key),
+        };
+        let synced_before = self.synced_at(&key);
+        if let Some(entry) = JournalEntry::new(log, key, before, synced_before) {
+            self.journal.record(chain_id, entry);
+        }
     }
 
-    pub async fn handle_v4_swap(
+    /// Drops the cached state of a pool so it is fetched again from the chain
+    pub fn refetch(&self, key: &AnyPoolKey) {
+        self.restore(
+            key,
+            match key {
+                AnyPoolKey::V2(_) => PoolSnapshot::V2(None),
+                AnyPoolKey::V3(_) => PoolSnapshot::V3(None),
+                AnyPoolKey::V4(_, _) => PoolSnapshot::V4(None),
+            },
+            None,
+        );
+
+        match key {
+            AnyPoolKey::V2(k) => self.enqueue_v2(k.clone()),
+            AnyPoolKey::V3(k) => self.enqueue_v3(k.clone()),
+            AnyPoolKey::V4(chain, id) => self.v4_fetch_worker.request(V4FetchArgs {
+                id: *id,
+                chain: *chain,
+            }),
+        }
+    }
+
+    /// Decodes a raw log of any registered pool event, failures are counted per topic0
+    pub fn decode_pool_log(
         &self,
-        log: Log<all_sol_types::sol_types::IPoolManager::Swap>,
-        chain_id: u64,
-    ) {
+        log: &alloy::rpc::types::Log,
+    ) -> Option<UnifiedPoolEventResponse> {
+        self.pool_decoder
+            .decode_or_defer(log, self.tokens.decoder())
+    }
+
+    pub fn pool_decode_failures(&self) -> Vec<(B256, u64)> {
+        self.pool_decoder.failures()
+    }
+
+    pub fn tokens(&self) -> &TokenTracker {
+        &self.tokens
+    }
+
+    /// Reverts the pool deltas applied from the block of a log the node marked as
+    /// `removed` when they were applied from the same block hash, refetching the pool when
+    /// the reorg is deeper than the journal.
+    pub fn handle_removed_log(&self, chain_id: u64, log: &alloy::rpc::types::Log) {
+        let Some(block) = log.block_number else {
+            return;
+        };
+
+        match self.journal.rollback(chain_id, block, log.block_hash) {
+            Some(reverted) => {
+                if !reverted.is_empty() {
+                    println!(
+                        "chain {} reorg at block {}, reverting {} deltas",
+                        chain_id,
+                        block,
+                        reverted.len()
+                    );
+                }
+                for entry in reverted {
+                    self.restore(&entry.pool, entry.before, entry.synced_before);
+                }
+            }
+            None => {
+                let key = self
+                    .pool_decoder
+                    .kind(log)
+                    .and_then(|e| e.pool_key(chain_id, log));
+                if let Some(key) = key {
+                    println!(
+                        "chain {} reorg at final block {}, refetching {:?}",
+                        chain_id, block, key
+                    );
+                    self.refetch(&key);
+                }
+            }
+        }
+    }
+
+    /// Last block whose logs were applied on the chain, live or backfilled
+    pub fn last_block(&self, chain_id: u64) -> Option<u64> {
+        self.last_blocks.get(&chain_id).map(|b| *b)
+    }
+
+    pub fn mark_block(&self, chain_id: u64, block: u64) {
+        self.last_blocks
+            .entry(chain_id)
+            .and_modify(|b| *b = (*b).max(block))
+            .or_insert(block);
+    }
+
+    /// Registers the chain provider, its v2/v3 factories and the v4 contracts of its dexes
+    pub fn add_chain(&self, chain_id: u64, provider: WsProvider, dexes: &[DexJsonModel]) {
+        for dex in dexes {
+            if let DexJsonModel::V2 {
+                address,
+                fee,
+                stable_fee,
+            } = dex
+                && let Ok(addr) = Address::from_str(address)
+            {
+                self.v2_dexes.insert(
+                    IdAddress {
+                        id: chain_id,
+                        address: addr,
+                    },
+                    PartialV2Dex {
+                        chain: chain_id,
+                        address: addr,
+                        id: Some(DexId::from_factory(chain_id, addr)),
+                        fees: Some(V2Fees {
+                            crypto: U24::from(*fee),
+                            stable: stable_fee.map(U24::from),
+                        }),
+                    },
+                );
+            }
+
+            if let DexJsonModel::V3 { address, fee } = dex
+                && let Ok(addr) = Address::from_str(address)
+            {
+                self.v3_dexes.insert(
+                    IdAddress {
+                        id: chain_id,
+                        address: addr,
+                    },
+                    PartialV3Dex {
+                        chain: chain_id,
+                        address: addr,
+                        id: Some(DexId::from_factory(chain_id, addr)),
+                        fees: Some(V3Fees {
+                            tiers: vec![U24::from(*fee)],
+                        }),
+                    },
+                );
+            }
+
+            if let Some(contracts) = V4Contracts::from_dex(dex, provider.clone()) {
+                self.v4_fetch_worker
+                    .add_contracts(chain_id, contracts.clone());
+                self.v4_contracts.insert(chain_id, contracts);
+            }
+        }
+        self.chains_providers.insert(chain_id, provider);
+    }
+
+    /// A v4 swap carries the whole pool state after it, pools whose `PoolKey` or tick
+    /// words are not known yet are sent to the v4 fetcher
+    pub fn handle_v4_swap(&self, log: Log<IPoolManager::Swap>, chain_id: u64) {
         let key = IdKey {
             id: chain_id,
-            key: log.inner.data.id,
+            key: log.inner.id,
         };
 
-        let args = V4FetchArgs {
-            id: log.inner.id,
-            chain: chain_id,
-            log: log,
+        let pool_key = AnyPoolKey::V4(chain_id, key.key);
+        if !self.is_new_log(&pool_key, &log) {
+            return;
+        }
+
+        self.journal(chain_id, &log, pool_key.clone());
+        let state = V3State {
+            tick: log.inner.tick,
+            x96price: log.inner.sqrtPriceX96,
+            liquidity: log.inner.liquidity,
+        };
+
+        let needs_fetch = {
+            let pool = self
+                .v4_pools
+                .entry(key.clone())
+                .and_modify(|x| x.state = Some(state.clone()))
+                .or_insert_with(|| PartialV4Pool {
+                    chain: chain_id,
+                    state_view: self
+                        .v4_contracts
+                        .get(&chain_id)
+                        .map(|c| *c.state_view.address())
+                        .unwrap_or_default(),
+                    config: None,
+                    state: Some(state),
+                    words: None,
+                    synced_at: None,
+                });
+            pool.config.is_none() || pool.words.is_none()
         };
+        self.mark_synced(&pool_key, &log);
 
-        let write_result = self
-            .v4_fetch_worker
-            .write()
-            .unwrap()
-            .handle_v4_swap(args, chain_id)
-            .await;
+        if needs_fetch {
+            self.v4_fetch_worker.request(V4FetchArgs {
+                id: key.key,
+                chain: chain_id,
+            });
+        }
     }
 
-    pub fn handle_v2_swap(
-        &self,
-        log: Log<all_sol_types::sol_types::IUniswapV2Pair::Swap>,
-        chain_id: u64,
-    ) {
-        let key = IdAddress {
+    /// `Initialize` carries the whole `PoolKey`, so pools created while indexing never
+    /// need the position manager lookup of the v4 fetcher
+    pub fn handle_v4_initialize(&self, log: Log<IPoolManager::Initialize>, chain_id: u64) {
+        let key = IdKey {
             id: chain_id,
-            address: log.address(),
+            key: log.inner.id,
         };
 
-        let mut needs_update = false;
+        let pool_key = AnyPoolKey::V4(chain_id, key.key);
+        if !self.is_new_log(&pool_key, &log) {
+            return;
+        }
 
-        self.v2_pools
+        self.journal(chain_id, &log, pool_key.clone());
+        let config = V4Config {
+            fee: log.inner.fee,
+            tick_spacing: log.inner.tickSpacing,
+            hooks: log.inner.hooks,
+            token0: log.inner.currency0,
+            token1: log.inner.currency1,
+        };
+        // a freshly initialized pool has no positions yet
+        let state = V3State {
+            tick: log.inner.tick,
+            x96price: log.inner.sqrtPriceX96,
+            liquidity: 0,
+        };
+
+        self.v4_pools
             .entry(key)
             .and_modify(|x| {
-                if let Some(stt) = x.state.as_mut() {
-                    stt.r0 += log.inner.amount0In.to::<u128>();
-                    stt.r1 += log.inner.amount1In.to::<u128>();
-                    stt.r0 -= log.inner.amount0Out.to::<u128>();
-                    stt.r1 -= log.inner.amount1Out.to::<u128>();
-                } else {
-                    needs_update = true;
-                }
+                x.config = Some(config.clone());
+                x.state = Some(state.clone());
             })
+            .or_insert_with(|| PartialV4Pool {
+                chain: chain_id,
+                state_view: self
+                    .v4_contracts
+                    .get(&chain_id)
+                    .map(|c| *c.state_view.address())
+                    .unwrap_or_default(),
+                config: Some(config),
+                state: Some(state),
+                words: None,
+                synced_at: None,
+            });
+        self.mark_synced(&pool_key, &log);
+    }
+
+    pub fn v4_fetcher(&self) -> Arc<V4Fetcher> {
+        self.v4_fetch_worker.clone()
+    }
+
+    /// `Sync` carries the reserves after every pair update, so it is the only event
+    /// allowed to write `V2State`
+    pub fn handle_v2_sync(&self, log: Log<IUniswapV2Pair::Sync>, chain_id: u64) {
+        let key = IdAddress {
+            id: chain_id,
+            address: log.address(),
+        };
+
+        let pool_key = AnyPoolKey::V2(key.clone());
+        if !self.is_new_log(&pool_key, &log) {
+            return;
+        }
+
+        self.journal(chain_id, &log, pool_key.clone());
+        let state = V2State {
+            r0: log.inner.reserve0.to::<u128>(),
+            r1: log.inner.reserve1.to::<u128>(),
+        };
+
+        let needs_config = self
+            .v2_pools
+            .entry(key.clone())
+            .and_modify(|x| x.state = Some(state.clone()))
             .or_insert_with(|| PartialV2Pool {
                 chain: chain_id,
                 address: log.address(),
+                dex: None,
+                provenance: None,
                 config: None,
-                state: None,
-            });
+                state: Some(state),
+                synced_at: None,
+            })
+            .config
+            .is_none();
+        self.mark_synced(&pool_key, &log);
 
-        if needs_update {
-            if let Ok(mut lock) = self.v2_reserves_queue.write() {
-                lock.push(IdAddress {
-                    id: chain_id,
+        if needs_config {
+            self.enqueue_v2(key);
+        }
+    }
+
+    /// Swaps only feed the pool stats, reserves come from `Sync`
+    pub fn handle_v2_swap(&self, log: Log<IUniswapV2Pair::Swap>, chain_id: u64) {
+        let key = IdAddress {
+            id: chain_id,
+            address: log.address(),
+        };
+
+        self.v2_swap_stats
+            .entry(key.clone())
+            .or_default()
+            .add(&log.inner);
+
+        let needs_update = {
+            let pool = self
+                .v2_pools
+                .entry(key.clone())
+                .or_insert_with(|| PartialV2Pool {
+                    chain: chain_id,
                     address: log.address(),
+                    dex: None,
+                    provenance: None,
+                    config: None,
+                    state: None,
+                    synced_at: None,
                 });
-            }
+            pool.config.is_none() || pool.state.is_none()
+        };
+
+        if needs_update {
+            self.enqueue_v2(key);
+        }
+    }
+
+    pub fn v2_swap_stats(&self, key: &IdAddress) -> Option<SwapStats> {
+        self.v2_swap_stats.get(key).map(|s| s.clone())
+    }
+
+    /// Queues a v2 pool for the reserves worker, a pool already queued is kept once
+    pub fn enqueue_v2(&self, key: IdAddress) {
+        if let Ok(mut lock) = self.v2_reserves_queue.write() {
+            lock.insert(key);
+        }
+    }
+
+    /// Queues a v2 pool whose fetch failed again, up to `MAX_FETCH_RETRIES` times in a row
+    pub fn retry_v2(&self, key: IdAddress) {
+        if self.retry_fetch(&AnyPoolKey::V2(key.clone())) {
+            self.enqueue_v2(key);
         }
     }
 
+    /// Counts a failed fetch of `key`, false once it failed too many times in a row
+    fn retry_fetch(&self, key: &AnyPoolKey) -> bool {
+        let mut failures = self.fetch_failures.entry(key.clone()).or_insert(0);
+        *failures += 1;
+        if *failures > MAX_FETCH_RETRIES {
+            println!(
+                "{:?} fetch failed {} times, waiting for its next log",
+                key, *failures
+            );
+            return false;
+        }
+        true
+    }
+
+    /// Drains the queue, skipping pools that got complete while they were waiting
+    pub fn take_v2_queue(&self) -> BTreeSet<IdAddress> {
+        let Ok(mut lock) = self.v2_reserves_queue.write() else {
+            return BTreeSet::new();
+        };
+
+        std::mem::take(&mut *lock)
+            .into_iter()
+            .filter(|k| {
+                self.v2_pools
+                    .get(k)
+                    .is_none_or(|p| p.config.is_none() || p.state.is_none())
+            })
+            .collect()
+    }
+
+    pub fn provider(&self, chain_id: u64) -> Option<WsProvider> {
+        self.chains_providers.get(&chain_id).map(|p| p.clone())
+    }
+
+    /// Fee of the v2 dex that deployed the pair, by its factory
+    pub fn v2_fee(&self, chain_id: u64, factory: Address) -> Option<U24> {
+        self.v2_dexes
+            .get(&IdAddress {
+                id: chain_id,
+                address: factory,
+            })
+            .and_then(|d| d.fees.as_ref().map(|f| f.crypto))
+    }
+
+    /// Writes fetched pair data read at `synced_at`, the reserves are dropped when a
+    /// `Sync` after that block was applied while the fetch was in flight
+    pub fn fill_v2_pool(
+        &self,
+        key: IdAddress,
+        config: V2Config,
+        state: V2State,
+        provenance: Option<PoolProvenance>,
+        synced_at: SyncPoint,
+    ) {
+        self.fetch_failures.remove(&AnyPoolKey::V2(key.clone()));
+        self.v2_pools
+            .entry(key.clone())
+            .and_modify(|x| {
+                x.config = Some(config.clone());
+                if provenance.is_some() {
+                    x.provenance = provenance;
+                }
+                if x.state.is_none() || synced_at.supersedes(x.synced_at.as_ref()) {
+                    x.state = Some(state.clone());
+                    x.synced_at = Some(synced_at);
+                }
+            })
+            .or_insert_with(|| PartialV2Pool {
+                chain: key.id,
+                address: key.address,
+                dex: None,
+                provenance,
+                config: Some(config),
+                state: Some(state),
+                synced_at: Some(synced_at),
+            });
+    }
+
+    /// A v3 swap carries the whole pool state after it, pools seen for the first time
+    /// are queued for hydration
     pub fn handle_v3_swap(&self, log: Log<V3Pool::Swap>, chain_id: u64) {
         let key = IdAddress {
             id: chain_id,
             address: log.address(),
         };
 
+        let pool_key = AnyPoolKey::V3(key.clone());
+        if !self.is_new_log(&pool_key, &log) {
+            return;
+        }
+
+        self.journal(chain_id, &log, pool_key.clone());
+        let state = V3State {
+            tick: log.inner.tick,
+            x96price: log.inner.sqrtPriceX96,
+            liquidity: log.inner.liquidity,
+        };
+
+        let needs_hydration = {
+            let pool = self
+                .v3_pools
+                .entry(key.clone())
+                .and_modify(|x| x.state = Some(state.clone()))
+                .or_insert_with(|| PartialV3Pool {
+                    chain: chain_id,
+                    address: log.address(),
+                    dex: None,
+                    provenance: None,
+                    config: None,
+                    state: Some(state),
+                    words: None,
+                    synced_at: None,
+                });
+            pool.config.is_none() || pool.words.is_none()
+        };
+        self.mark_synced(&pool_key, &log);
+
+        if needs_hydration {
+            self.enqueue_v3(key);
+        }
+    }
+
+    /// Queues a v3 pool for the hydration worker, a pool already queued is kept once
+    pub fn enqueue_v3(&self, key: IdAddress) {
+        if let Ok(mut lock) = self.v3_hydrate_queue.write() {
+            lock.insert(key);
+        }
+    }
+
+    /// Queues a v3 pool whose fetch failed again, up to `MAX_FETCH_RETRIES` times in a row
+    pub fn retry_v3(&self, key: IdAddress) {
+        if self.retry_fetch(&AnyPoolKey::V3(key.clone())) {
+            self.enqueue_v3(key);
+        }
+    }
+
+    /// Drains the queue, skipping pools that got hydrated while they were waiting
+    pub fn take_v3_queue(&self) -> BTreeSet<IdAddress> {
+        let Ok(mut lock) = self.v3_hydrate_queue.write() else {
+            return BTreeSet::new();
+        };
+
+        std::mem::take(&mut *lock)
+            .into_iter()
+            .filter(|k| {
+                self.v3_pools
+                    .get(k)
+                    .is_none_or(|p| p.config.is_none() || p.state.is_none() || p.words.is_none())
+            })
+            .collect()
+    }
+
+    /// Writes a hydrated pool read at `synced_at`. When logs after that block were
+    /// applied while the fetch was in flight the cached state is more recent, and the
+    /// fetched words only fill the positions that are missing, with the mints and burns
+    /// they missed since `synced_at` replayed onto them
+    pub fn fill_v3_pool(
+        &self,
+        key: IdAddress,
+        config: V3Config,
+        state: V3State,
+        words: PoolWords,
+        provenance: Option<PoolProvenance>,
+        synced_at: SyncPoint,
+    ) {
+        self.fetch_failures.remove(&AnyPoolKey::V3(key.clone()));
         self.v3_pools
-            .entry(key)
-            .and_modify(|x| match x.state.as_mut() {
-                Some(stt) => {
-                    stt.liquidity = log.inner.liquidity;
-                    stt.tick = log.inner.tick;
-                    stt.x96price = log.inner.sqrtPriceX96;
-                }
-                None => {
-                    let p = PartialV3Pool {
-                        chain: chain_id,
-                        address: log.address(),
-                        config: x.config.clone(),
-                        state: Some(V3State {
-                            tick: log.inner.tick,
-                            x96price: log.inner.sqrtPriceX96,
-                            liquidity: log.inner.liquidity,
-                        }),
-                    };
+            .entry(key.clone())
+            .and_modify(|x| {
+                x.config = Some(config.clone());
+                if provenance.is_some() {
+                    x.provenance = provenance;
+                }
+                let newer = x.state.is_none() || synced_at.supersedes(x.synced_at.as_ref());
+                if newer {
+                    x.state = Some(state.clone());
+                    x.synced_at = Some(synced_at);
+                }
+                match x.words.as_mut() {
+                    Some(current) if newer => current.words.extend(words.words.clone()),
+                    Some(current) => {
+                        let mut missing = PoolWords::default();
+                        for (pos, word) in &words.words {
+                            if !current.words.contains_key(pos) {
+                                missing.words.insert(*pos, word.clone());
+                            }
+                        }
+                        self.replay_skipped(&key, &mut missing, &synced_at, config.tick_spacing);
+                        current.words.extend(missing.words);
+                    }
+                    None => {
+                        let mut words = words.clone();
+                        self.replay_skipped(&key, &mut words, &synced_at, config.tick_spacing);
+                        x.words = Some(words);
+                    }
+                }
+                if let Some(words) = x.words.as_ref() {
+                    self.forget_skipped(&key, words, config.tick_spacing);
                 }
             })
             .or_insert_with(|| PartialV3Pool {
+                chain: key.id,
+                address: key.address,
+                dex: None,
+                provenance,
+                config: Some(config),
+                state: Some(state),
+                words: Some(words),
+                synced_at: Some(synced_at),
+            });
+    }
+
+    /// Applies to `words`, read at `read_at`, the skipped mints and burns that came after
+    fn replay_skipped(
+        &self,
+        key: &IdAddress,
+        words: &mut PoolWords,
+        read_at: &SyncPoint,
+        tick_spacing: I24,
+    ) {
+        let Some(skipped) = self.skipped_positions.get(key) else {
+            return;
+        };
+        for p in skipped
+            .iter()
+            .filter(|p| read_at.precedes_log(p.at.block, p.at.log_index))
+        {
+            words.update_position(p.tick_lower, p.tick_upper, tick_spacing, p.liquidity_delta);
+        }
+    }
+
+    /// Drops the skipped mints and burns whose words are all in `words` now, they either
+    /// were applied to them or replayed onto them
+    fn forget_skipped(&self, key: &IdAddress, words: &PoolWords, tick_spacing: I24) {
+        if let Some(mut skipped) = self.skipped_positions.get_mut(key) {
+            skipped.retain(|p| misses_word(words, p.tick_lower, p.tick_upper, tick_spacing));
+        }
+    }
+
+    pub fn handle_v3_mint(&self, log: Log<V3Pool::Mint>, chain_id: u64) {
+        let Ok(delta) = i128::try_from(log.inner.amount) else {
+            println!("v3 mint amount out of range, skipping it: {:?}", log);
+            return;
+        };
+        let (lower, upper) = (log.inner.tickLower, log.inner.tickUpper);
+        self.update_v3_position(&log, chain_id, lower, upper, delta);
+    }
+
+    pub fn handle_v3_burn(&self, log: Log<V3Pool::Burn>, chain_id: u64) {
+        let Ok(delta) = i128::try_from(log.inner.amount) else {
+            println!("v3 burn amount out of range, skipping it: {:?}", log);
+            return;
+        };
+        let (lower, upper) = (log.inner.tickLower, log.inner.tickUpper);
+        self.update_v3_position(&log, chain_id, lower, upper, -delta);
+    }
+
+    /// Moves `liquidity_delta` in or out of a range, updating the ticks at both ends and
+    /// the active liquidity when the current tick is inside it
+    fn update_v3_position<T>(
+        &self,
+        log: &Log<T>,
+        chain_id: u64,
+        tick_lower: I24,
+        tick_upper: I24,
+        liquidity_delta: i128,
+    ) {
+        let key = IdAddress {
+            id: chain_id,
+            address: log.address(),
+        };
+        // burns of 0 only collect fees
+        if liquidity_delta == 0 {
+            return;
+        }
+
+        if !self.v3_pools.contains_key(&key) {
+            self.enqueue_v3(key);
+            return;
+        }
+
+        let pool_key = AnyPoolKey::V3(key.clone());
+        if !self.is_new_log(&pool_key, log) {
+            return;
+        }
+
+        self.journal_position(chain_id, log, pool_key.clone());
+        let mut skipped = false;
+        if let Some(mut pool) = self.v3_pools.get_mut(&key) {
+            let tick_spacing = pool.config.as_ref().map(|c| c.tick_spacing);
+            let PartialV3Pool { state, words, .. } = &mut *pool;
+            skipped = match (words.as_ref(), tick_spacing) {
+                (Some(words), Some(spacing)) => misses_word(words, tick_lower, tick_upper, spacing),
+                _ => true,
+            };
+            apply_position(
+                state.as_mut(),
+                words.as_mut().zip(tick_spacing),
+                tick_lower,
+                tick_upper,
+                liquidity_delta,
+            );
+        }
+        if let (true, Some(block)) = (skipped, log.block_number) {
+            let finalized = self.finalized_block(chain_id);
+            let mut positions = self.skipped_positions.entry(key).or_default();
+            positions.retain(|p| finalized.is_none_or(|f| p.at.block > f));
+            positions.push(SkippedPosition {
+                at: SyncPoint::at_log(block, log.block_hash, log.log_index),
+                tick_lower,
+                tick_upper,
+                liquidity_delta,
+            });
+        }
+        self.mark_synced(&pool_key, log);
+    }
+
+    pub fn handle_v4_modify(&self, log: Log<IPoolManager::ModifyLiquidity>, chain_id: u64) {
+        let key = IdKey {
+            id: chain_id,
+            key: log.inner.id,
+        };
+        let Ok(liquidity_delta) = i128::try_from(log.inner.liquidityDelta) else {
+            println!("v4 pool {:?} liquidity delta out of range", key);
+            return;
+        };
+        if liquidity_delta == 0 {
+            return;
+        }
+
+        if !self.v4_pools.contains_key(&key) {
+            self.v4_fetch_worker.request(V4FetchArgs {
+                id: key.key,
                 chain: chain_id,
-                address: log.address(),
-                config: None,
-                state: None,
             });
+            return;
+        }
+
+        let pool_key = AnyPoolKey::V4(chain_id, key.key);
+        if !self.is_new_log(&pool_key, &log) {
+            return;
+        }
+
+        self.journal_position(chain_id, &log, pool_key.clone());
+        if let Some(mut pool) = self.v4_pools.get_mut(&key) {
+            let tick_spacing = pool.config.as_ref().map(|c| c.tick_spacing);
+            let PartialV4Pool { state, words, .. } = &mut *pool;
+            apply_position(
+                state.as_mut(),
+                words.as_mut().zip(tick_spacing),
+                log.inner.tickLower,
+                log.inner.tickUpper,
+                liquidity_delta,
+            );
+        }
+        self.mark_synced(&pool_key, &log);
+    }
+
+    /// Registers a pair found through a configured factory, which makes it trusted, the
+    /// v2 fetcher fills whatever is still missing
+    pub fn register_v2_pool(&self, key: IdAddress, dex: DexId, config: Option<V2Config>) {
+        let complete = {
+            let pool = self
+                .v2_pools
+                .entry(key.clone())
+                .and_modify(|x| {
+                    x.dex.get_or_insert(dex);
+                    x.provenance = Some(PoolProvenance::Trusted);
+                    if config.is_some() {
+                        x.config = config.clone();
+                    }
+                })
+                .or_insert_with(|| PartialV2Pool {
+                    chain: key.id,
+                    address: key.address,
+                    dex: Some(dex),
+                    provenance: Some(PoolProvenance::Trusted),
+                    config: config.clone(),
+                    state: None,
+                    synced_at: None,
+                });
+            pool.config.is_some() && pool.state.is_some()
+        };
+
+        if !complete {
+            self.enqueue_v2(key);
+        }
+    }
+
+    /// Registers a pool found through a configured factory, which makes it trusted, the v3
+    /// fetcher loads its state and words
+    pub fn register_v3_pool(&self, key: IdAddress, dex: DexId, config: V3Config) {
+        let complete = {
+            let pool = self
+                .v3_pools
+                .entry(key.clone())
+                .and_modify(|x| {
+                    x.dex.get_or_insert(dex);
+                    x.provenance = Some(PoolProvenance::Trusted);
+                    x.config = Some(config.clone());
+                })
+                .or_insert_with(|| PartialV3Pool {
+                    chain: key.id,
+                    address: key.address,
+                    dex: Some(dex),
+                    provenance: Some(PoolProvenance::Trusted),
+                    config: Some(config.clone()),
+                    state: None,
+                    words: None,
+                    synced_at: None,
+                });
+            pool.state.is_some() && pool.words.is_some()
+        };
+
+        if !complete {
+            self.enqueue_v3(key);
+        }
+    }
+
+    /// `None` while the pool was not verified yet, v4 pools are not tracked
+    pub fn pool_provenance(&self, key: &AnyPoolKey) -> Option<PoolProvenance> {
+        match key {
+            AnyPoolKey::V2(k) => self.v2_pools.get(k)?.provenance,
+            AnyPoolKey::V3(k) => self.v3_pools.get(k)?.provenance,
+            AnyPoolKey::V4(_, _) => None,
+        }
+    }
+
+    /// Factory of a configured v2 dex, `None` for any other contract emitting `PairCreated`
+    pub fn v2_dex(&self, chain_id: u64, factory: Address) -> Option<DexId> {
+        self.v2_dexes
+            .get(&IdAddress {
+                id: chain_id,
+                address: factory,
+            })
+            .map(|d| d.id.unwrap_or(DexId::Unknown))
+    }
+
+    pub fn v3_dex(&self, chain_id: u64, factory: Address) -> Option<DexId> {
+        self.v3_dexes
+            .get(&IdAddress {
+                id: chain_id,
+                address: factory,
+            })
+            .map(|d| d.id.unwrap_or(DexId::Unknown))
+    }
+
+    pub fn handle_v2_pair_created(&self, log: Log<IUniswapV2Factory::PairCreated>, chain_id: u64) {
+        let factory = log.address();
+        let (Some(dex), Some(fee)) = (
+            self.v2_dex(chain_id, factory),
+            self.v2_fee(chain_id, factory),
+        ) else {
+            return;
+        };
+
+        let config = V2Config {
+            name: "".to_string(),
+            fee,
+            token0: log.inner.token0,
+            token1: log.inner.token1,
+        };
+        let key = IdAddress {
+            id: chain_id,
+            address: log.inner.pair,
+        };
+        self.register_v2_pool(key, dex, Some(config));
+    }
+
+    pub fn handle_v3_pool_created(&self, log: Log<IUniswapV3Factory::PoolCreated>, chain_id: u64) {
+        let Some(dex) = self.v3_dex(chain_id, log.address()) else {
+            return;
+        };
+
+        let config = V3Config {
+            name: "".to_string(),
+            fee: log.inner.fee,
+            tick_spacing: log.inner.tickSpacing,
+            token0: log.inner.token0,
+            token1: log.inner.token1,
+        };
+        let key = IdAddress {
+            id: chain_id,
+            address: log.inner.pool,
+        };
+        self.register_v3_pool(key, dex, config);
+    }
+
+    /// Simulates selling `amount_in` of token0 when `from0`, or of token1, on a hydrated
+    /// v3 pool. The window is recentered on the current tick first, then the words the
+    /// trade runs into are loaded as it goes, all at the block the pool was synced at.
+    ///
+    /// `None` while the pool is not hydrated
+    pub async fn simulate_v3_trade(
+        &self,
+        key: &IdAddress,
+        amount_in: U256,
+        from0: bool,
+        sqrt_price_limit: Option<U256>,
+    ) -> Option<Result<TradeOutcome, TradeError>> {
+        let (config, state, mut words, synced_at) = {
+            let pool = self.v3_pools.get(key)?;
+            (
+                pool.config.clone()?,
+                pool.state.clone()?,
+                pool.words.clone()?,
+                pool.synced_at,
+            )
+        };
+        let block = synced_at.as_ref().map_or(BlockId::latest(), pinned_block);
+        let source = V3Words {
+            batcher: MulticallBatcher::new(self.provider(key.id)?).at_block(block),
+            pool: key.address,
+            tick_spacing: config.tick_spacing,
+        };
+
+        for pos in self
+            .tick_window
+            .recenter(&source, &mut words, state.tick)
+            .await
+        {
+            self.merge_v3_word(key, pos, words.words[&pos].clone(), synced_at);
+        }
+        let mut pool = to_trade_state(&state, &words, config.tick_spacing);
+        let outcome = trade_math::trade(&pool, &config.fee, amount_in, from0, sqrt_price_limit);
+        let merge = |pos, word| self.merge_v3_word(key, pos, word, synced_at);
+        Some(
+            self.tick_window
+                .extend(&source, &mut pool, outcome, merge)
+                .await,
+        )
+    }
+
+    /// V4 pools share the v3 swap math, so they are simulated the same way, their words
+    /// are read through the `StateView`
+    pub async fn simulate_v4_trade(
+        &self,
+        key: &IdKey,
+        amount_in: U256,
+        from0: bool,
+        sqrt_price_limit: Option<U256>,
+    ) -> Option<Result<TradeOutcome, TradeError>> {
+        let (config, state, mut words, synced_at, state_view) = {
+            let pool = self.v4_pools.get(key)?;
+            (
+                pool.config.clone()?,
+                pool.state.clone()?,
+                pool.words.clone()?,
+                pool.synced_at,
+                pool.state_view,
+            )
+        };
+        let block = synced_at.as_ref().map_or(BlockId::latest(), pinned_block);
+        let source = V4Words {
+            batcher: MulticallBatcher::new(self.provider(key.id)?).at_block(block),
+            state_view,
+            id: key.key,
+            tick_spacing: config.tick_spacing,
+        };
+
+        for pos in self
+            .tick_window
+            .recenter(&source, &mut words, state.tick)
+            .await
+        {
+            self.merge_v4_word(key, pos, words.words[&pos].clone(), synced_at);
+        }
+        let mut pool = to_trade_state(&state, &words, config.tick_spacing);
+        let outcome = trade_math::trade(&pool, &config.fee, amount_in, from0, sqrt_price_limit);
+        let merge = |pos, word| self.merge_v4_word(key, pos, word, synced_at);
+        Some(
+            self.tick_window
+                .extend(&source, &mut pool, outcome, merge)
+                .await,
+        )
+    }
+
+    /// Adds a word loaded past the window of a v3 pool. The word was read at `read_at`,
+    /// it is dropped when logs moved the pool since as they skipped the missing word
+    pub fn merge_v3_word(
+        &self,
+        key: &IdAddress,
+        pos: i16,
+        word: TicksBitMap,
+        read_at: Option<SyncPoint>,
+    ) {
+        let Some(mut pool) = self.v3_pools.get_mut(key) else {
+            return;
+        };
+        if pool.synced_at != read_at {
+            return;
+        }
+        if let Some(words) = pool.words.as_mut() {
+            words.words.entry(pos).or_insert(word);
+        }
+    }
+
+    /// `merge_v3_word` for v4 pools
+    pub fn merge_v4_word(
+        &self,
+        key: &IdKey,
+        pos: i16,
+        word: TicksBitMap,
+        read_at: Option<SyncPoint>,
+    ) {
+        let Some(mut pool) = self.v4_pools.get_mut(key) else {
+            return;
+        };
+        if pool.synced_at != read_at {
+            return;
+        }
+        if let Some(words) = pool.words.as_mut() {
+            words.words.entry(pos).or_insert(word);
+        }
     }
 }
 
-impl From<ChainDataJsonModel> for MasterContext {
-    fn from(value: ChainDataJsonModel) -> Self {}
+/// Whether the word of either end of a position isn't loaded
+fn misses_word(words: &PoolWords, tick_lower: I24, tick_upper: I24, tick_spacing: I24) -> bool {
+    [tick_lower, tick_upper].iter().any(|tick| {
+        !words
+            .words
+            .contains_key(&get_pos_from_tick(*tick, tick_spacing))
+    })
+}
+
+fn apply_position(
+    state: Option<&mut V3State>,
+    words: Option<(&mut PoolWords, I24)>,
+    tick_lower: I24,
+    tick_upper: I24,
+    liquidity_delta: i128,
+) {
+    if let Some(state) = state
+        && tick_lower <= state.tick
+        && state.tick < tick_upper
+    {
+        state.liquidity = state.liquidity.saturating_add_signed(liquidity_delta);
+    }
+
+    if let Some((words, tick_spacing)) = words {
+        words.update_position(tick_lower, tick_upper, tick_spacing, liquidity_delta);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use alloy::primitives::aliases::U160;
+    use shape::p_ticks::TickData;
+
+    use super::*;
+
+    fn word(ticks: &[(i32, i128)]) -> TicksBitMap {
+        TicksBitMap {
+            bitmap: U256::ZERO,
+            ticks: ticks
+                .iter()
+                .map(|(tick, net)| {
+                    (
+                        I24::try_from(*tick).unwrap(),
+                        TickData {
+                            liquidity_net: Some(*net),
+                            liquidity_gross: Some(net.unsigned_abs()),
+                        },
+                    )
+                })
+                .collect::<BTreeMap<_, _>>(),
+        }
+    }
+
+    #[test]
+    fn words_fetched_before_a_mint_get_it_replayed() {
+        let ctx = MasterContext::new(12, TickWindow::default());
+        let key = IdAddress {
+            id: 1,
+            address: Address::repeat_byte(1),
+        };
+        let config = V3Config {
+            name: "".to_string(),
+            fee: U24::from(3000),
+            tick_spacing: I24::try_from(60).unwrap(),
+            token0: Address::repeat_byte(2),
+            token1: Address::repeat_byte(3),
+        };
+        let state = V3State {
+            tick: I24::ZERO,
+            x96price: U160::from(1) << 96,
+            liquidity: 0,
+        };
+        let pool_words = |words: &[(i16, TicksBitMap)]| PoolWords {
+            words: words.iter().cloned().collect(),
+        };
+        let hash = B256::repeat_byte(9);
+        ctx.fill_v3_pool(
+            key.clone(),
+            config.clone(),
+            state.clone(),
+            pool_words(&[(0, word(&[]))]),
+            None,
+            SyncPoint::at_block(100, hash),
+        );
+
+        // a mint in word 1, which isn't loaded yet
+        let mint = Log {
+            block_hash: Some(hash),
+            inner: alloy::primitives::Log {
+                address: key.address,
+                data: V3Pool::Mint {
+                    sender: Address::ZERO,
+                    owner: Address::ZERO,
+                    tickLower: I24::try_from(15360).unwrap(),
+                    tickUpper: I24::try_from(15420).unwrap(),
+                    amount: 500,
+                    amount0: U256::ZERO,
+                    amount1: U256::ZERO,
+                },
+            },
+            block_number: Some(102),
+            block_timestamp: None,
+            transaction_hash: None,
+            transaction_index: None,
+            log_index: Some(0),
+            removed: false,
+        };
+        ctx.handle_v3_mint(mint, 1);
+
+        // a fetch read before the mint brings word 1 in
+        ctx.fill_v3_pool(
+            key.clone(),
+            config,
+            state,
+            pool_words(&[(0, word(&[])), (1, word(&[(15360, 100), (15420, -100)]))]),
+            None,
+            SyncPoint::at_block(101, hash),
+        );
+        let pool = ctx.v3_pools.get(&key).unwrap();
+        let ticks = &pool.words.as_ref().unwrap().words[&1].ticks;
+        let net = |tick: i32| ticks[&I24::try_from(tick).unwrap()].liquidity_net;
+        assert_eq!(net(15360), Some(600));
+        assert_eq!(net(15420), Some(-600));
+        assert!(ctx.skipped_positions.get(&key).unwrap().is_empty());
+    }
 }
@@ -13,8 +13,12 @@ use alloy::{
     transports::{RpcError, TransportErrorKind, http::reqwest::Url, ws::WsConnect},
 };
 use futures::channel::mpsc::UnboundedReceiver;
+use shape::{id_address::IdAddress, p_key::AnyPoolKey};
 
-use crate::MasterContext;
+use crate::{
+    MasterContext,
+    event_registry::{EventRegistry, event_registry},
+};
 
 pub struct Chunk {
     addrs: HashSet<Address>,
@@ -22,136 +26,276 @@ pub struct Chunk {
     id: u32,
 }
 
-pub struct PoolEvents {
-    map: HashMap<alloy::primitives::FixedBytes<32>, UnifiedPoolEvent>,
+event_registry! {
+    /// Every event the pool listeners decode, the subscription filter and the topic0
+    /// map are both derived from this list
+    pub enum UnifiedPoolEvent => UnifiedPoolEventResponse {
+        // Uniswap V2
+        sol_types::IUniswapV2Factory::PairCreated => V2PairCreated,
+        sol_types::IUniswapV2Pair::Mint => V2Mint,
+        sol_types::IUniswapV2Pair::Burn => V2Burn,
+        sol_types::IUniswapV2Pair::Swap => V2Swap,
+        sol_types::IUniswapV2Pair::Sync => V2Sync,
+        sol_types::IUniswapV2Pair::Approval => V2Approval,
+        sol_types::IUniswapV2Pair::Transfer => V2Transfer,
+        // Uniswap V3
+        sol_types::IUniswapV3Factory::PoolCreated => V3PoolCreated,
+        sol_types::V3Pool::Mint => V3Mint,
+        sol_types::V3Pool::Swap => V3Swap,
+        sol_types::V3Pool::Collect => V3Collect,
+        sol_types::V3Pool::Burn => V3Burn,
+        sol_types::V3Pool::Flash => V3Flash,
+        // Uniswap V4
+        IPoolManager::Initialize => V4Initialize,
+        IPoolManager::ModifyLiquidity => V4Modify,
+        IPoolManager::Swap => V4Swap,
+        IPoolManager::Donate => V4Donate,
+    }
 }
 
-pub fn generate_pools_events_map()
--> std::collections::HashMap<alloy::primitives::FixedBytes<32>, UnifiedPoolEvent> {
-    let mut map = HashMap::new();
-
-    let v2_sync_hash = sol_types::IUniswapV2Pair::Sync::SIGNATURE_HASH;
-    map.insert(v2_sync_hash, UnifiedPoolEvent::V2Mint());
-
-    let v2_mint_hash = sol_types::IUniswapV2Pair::Mint::SIGNATURE_HASH;
-    map.insert(v2_mint_hash, UnifiedPoolEvent::V2Mint());
-
-    let v2_approval_hash = sol_types::IUniswapV2Pair::Approval::SIGNATURE_HASH;
-
-    map.insert(v2_approval_hash, UnifiedPoolEvent::V2Approval());
-
-    let v2_swap_hash = sol_types::IUniswapV2Pair::Swap::SIGNATURE_HASH;
-
-    map.insert(v2_swap_hash, UnifiedPoolEvent::V2Swap());
-
-    let v2_burn_hash = sol_types::IUniswapV2Pair::Burn::SIGNATURE_HASH;
+pub fn generate_pool_events() -> Vec<&'static str> {
+    UnifiedPoolEvent::signatures()
+}
 
-    map.insert(v2_burn_hash, UnifiedPoolEvent::V2Burn());
+impl UnifiedPoolEvent {
+    /// Pool a raw log of this kind belongs to, v4 pools are identified by the indexed pool id
+    pub fn pool_key(&self, chain_id: u64, log: &Log) -> Option<AnyPoolKey> {
+        let key = match self {
+            // factory events create pools, they don't change the state of one
+            UnifiedPoolEvent::V2PairCreated() | UnifiedPoolEvent::V3PoolCreated() => return None,
+            UnifiedPoolEvent::V2Mint()
+            | UnifiedPoolEvent::V2Burn()
+            | UnifiedPoolEvent::V2Swap()
+            | UnifiedPoolEvent::V2Sync()
+            | UnifiedPoolEvent::V2Approval()
+            | UnifiedPoolEvent::V2Transfer() => AnyPoolKey::V2(IdAddress {
+                id: chain_id,
+                address: log.address(),
+            }),
+            UnifiedPoolEvent::V3Mint()
+            | UnifiedPoolEvent::V3Swap()
+            | UnifiedPoolEvent::V3Collect()
+            | UnifiedPoolEvent::V3Burn()
+            | UnifiedPoolEvent::V3Flash() => AnyPoolKey::V3(IdAddress {
+                id: chain_id,
+                address: log.address(),
+            }),
+            UnifiedPoolEvent::V4Donate()
+            | UnifiedPoolEvent::V4Initialize()
+            | UnifiedPoolEvent::V4Modify()
+            | UnifiedPoolEvent::V4Swap() => AnyPoolKey::V4(chain_id, *log.topics().get(1)?),
+        };
+        Some(key)
+    }
+}
 
-    let v3_burn_hash = sol_types::V3Pool::Burn::SIGNATURE_HASH;
-    map.insert(v3_burn_hash, UnifiedPoolEvent::V3Burn());
+impl UnifiedPoolEventResponse {
+    pub fn handle(&self, ctx: &MasterContext, chain_id: u64) {
+        match self {
+            UnifiedPoolEventResponse::V2PairCreated(log) => {
+                ctx.handle_v2_pair_created(log.to_owned(), chain_id)
+            }
+            UnifiedPoolEventResponse::V2Mint(log) => {}
+            UnifiedPoolEventResponse::V2Burn(log) => {}
+            UnifiedPoolEventResponse::V2Swap(log) => ctx.handle_v2_swap(log.to_owned(), chain_id),
+            UnifiedPoolEventResponse::V2Sync(log) => ctx.handle_v2_sync(log.to_owned(), chain_id),
+            UnifiedPoolEventResponse::V2Approval(log) => {}
+            UnifiedPoolEventResponse::V2Transfer(log) => {}
+            UnifiedPoolEventResponse::V3PoolCreated(log) => {
+                ctx.handle_v3_pool_created(log.to_owned(), chain_id)
+            }
+            UnifiedPoolEventResponse::V3Mint(log) => ctx.handle_v3_mint(log.to_owned(), chain_id),
+            UnifiedPoolEventResponse::V3Swap(log) => ctx.handle_v3_swap(log.to_owned(), chain_id),
+            UnifiedPoolEventResponse::V3Collect(log) => {}
+            UnifiedPoolEventResponse::V3Burn(log) => ctx.handle_v3_burn(log.to_owned(), chain_id),
+            UnifiedPoolEventResponse::V3Flash(log) => {}
+            UnifiedPoolEventResponse::V4Donate(log) => {}
+            UnifiedPoolEventResponse::V4Initialize(log) => {
+                ctx.handle_v4_initialize(log.to_owned(), chain_id)
+            }
+            UnifiedPoolEventResponse::V4Modify(log) => {
+                ctx.handle_v4_modify(log.to_owned(), chain_id)
+            }
+            UnifiedPoolEventResponse::V4Swap(log) => ctx.handle_v4_swap(log.to_owned(), chain_id),
+        }
+    }
+}
 
-    let v3_collect_hash = sol_types::V3Pool::Collect::SIGNATURE_HASH;
-    map.insert(v3_collect_hash, UnifiedPoolEvent::V3Collect());
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::{Address, LogData, keccak256};
 
-    let v3_mint_hash = sol_types::V3Pool::Mint::SIGNATURE_HASH;
-    map.insert(v3_mint_hash, UnifiedPoolEvent::V3Mint());
+    use super::*;
+    use crate::{event_registry::EventDecoder, token_event::UnifiedTokenEvent};
 
-    let v3_flash_hash = sol_types::V3Pool::Flash::SIGNATURE_HASH;
-    map.insert(v3_flash_hash, UnifiedPoolEvent::V3Flash());
+    /// canonical signatures written out by hand, so a wrong entry in the registry can't
+    /// also produce the expected topic0
+    const EXPECTED: &[(&str, UnifiedPoolEvent)] = &[
+        (
+            "PairCreated(address,address,address,uint256)",
+            UnifiedPoolEvent::V2PairCreated(),
+        ),
+        ("Mint(address,uint256,uint256)", UnifiedPoolEvent::V2Mint()),
+        (
+            "Burn(address,uint256,uint256,address)",
+            UnifiedPoolEvent::V2Burn(),
+        ),
+        (
+            "Swap(address,uint256,uint256,uint256,uint256,address)",
+            UnifiedPoolEvent::V2Swap(),
+        ),
+        ("Sync(uint112,uint112)", UnifiedPoolEvent::V2Sync()),
+        (
+            "Approval(address,address,uint256)",
+            UnifiedPoolEvent::V2Approval(),
+        ),
+        (
+            "Transfer(address,address,uint256)",
+            UnifiedPoolEvent::V2Transfer(),
+        ),
+        (
+            "PoolCreated(address,address,uint24,int24,address)",
+            UnifiedPoolEvent::V3PoolCreated(),
+        ),
+        (
+            "Mint(address,address,int24,int24,uint128,uint256,uint256)",
+            UnifiedPoolEvent::V3Mint(),
+        ),
+        (
+            "Swap(address,address,int256,int256,uint160,uint128,int24)",
+            UnifiedPoolEvent::V3Swap(),
+        ),
+        (
+            "Collect(address,address,int24,int24,uint128,uint128)",
+            UnifiedPoolEvent::V3Collect(),
+        ),
+        (
+            "Burn(address,int24,int24,uint128,uint256,uint256)",
+            UnifiedPoolEvent::V3Burn(),
+        ),
+        (
+            "Flash(address,address,uint256,uint256,uint256,uint256)",
+            UnifiedPoolEvent::V3Flash(),
+        ),
+        (
+            "Initialize(bytes32,address,address,uint24,int24,address,uint160,int24)",
+            UnifiedPoolEvent::V4Initialize(),
+        ),
+        (
+            "ModifyLiquidity(bytes32,address,int24,int24,int256,bytes32)",
+            UnifiedPoolEvent::V4Modify(),
+        ),
+        (
+            "Swap(bytes32,address,int128,int128,uint160,uint128,int24,uint24)",
+            UnifiedPoolEvent::V4Swap(),
+        ),
+        (
+            "Donate(bytes32,address,uint256,uint256)",
+            UnifiedPoolEvent::V4Donate(),
+        ),
+    ];
 
-    let v3_swap_hash = sol_types::V3Pool::Swap::SIGNATURE_HASH;
-    map.insert(v3_swap_hash, UnifiedPoolEvent::V3Swap());
+    #[test]
+    fn topic0_maps_to_intended_variant() {
+        let map = UnifiedPoolEvent::events_map();
+        for (signature, variant) in EXPECTED {
+            assert_eq!(
+                map.get(&keccak256(signature)),
+                Some(&vec![variant.clone()]),
+                "{}",
+                signature
+            );
+        }
+        assert_eq!(map.len(), EXPECTED.len());
+    }
 
-    let v4_modify_liquidity = sol_types::StateView::ModifyLiquidity::SIGNATURE_HASH;
-    map.insert(v4_modify_liquidity, UnifiedPoolEvent::V4Modify());
+    #[test]
+    fn filter_covers_every_registered_event() {
+        let signatures = generate_pool_events();
+        assert_eq!(signatures.len(), UnifiedPoolEvent::EVENTS.len());
 
-    let v4_swap = sol_types::IPoolManager::Swap::SIGNATURE_HASH;
-    map.insert(v4_swap, UnifiedPoolEvent::V4Swap());
+        for (signature, _) in EXPECTED {
+            assert!(signatures.contains(signature), "{}", signature);
+        }
+    }
 
-    let v4_init = sol_types::IPoolManager::ModifyLiquidity::SIGNATURE_HASH;
-    map.insert(v4_init, UnifiedPoolEvent::V4Initialize());
+    #[test]
+    fn registry_has_no_duplicate_topics() {
+        let topics: HashSet<B256> = UnifiedPoolEvent::EVENTS
+            .iter()
+            .map(|(_, t, _)| *t)
+            .collect();
+        assert_eq!(topics.len(), UnifiedPoolEvent::EVENTS.len());
+    }
 
-    let v4_donate = sol_types::IPoolManager::Donate::SIGNATURE_HASH;
-    map.insert(v4_donate, UnifiedPoolEvent::V4Donate());
+    #[test]
+    fn logs_decode_through_registry() {
+        let decoder = EventDecoder::<UnifiedPoolEvent>::default();
+        let sync = sol_types::IUniswapV2Pair::Sync {
+            reserve0: alloy::primitives::aliases::U112::from(1),
+            reserve1: alloy::primitives::aliases::U112::from(2),
+        };
+        let log = Log {
+            inner: alloy::primitives::Log {
+                address: Address::ZERO,
+                data: sync.encode_log_data(),
+            },
+            ..Default::default()
+        };
+        assert!(matches!(
+            decoder.decode(&log),
+            Some(UnifiedPoolEventResponse::V2Sync(_))
+        ));
 
-    map
-}
+        let unknown = Log {
+            inner: alloy::primitives::Log {
+                address: Address::ZERO,
+                data: LogData::new_unchecked(vec![keccak256("Unknown()")], Default::default()),
+            },
+            ..Default::default()
+        };
+        assert!(decoder.decode(&unknown).is_none());
+        assert!(decoder.failures().is_empty());
+    }
 
-pub fn generate_pool_events() -> Vec<&'static str> {
-    let v2_events = sol_types::IUniswapV2Pair::IUniswapV2PairEvents::SIGNATURES.clone();
-    let v3_events = sol_types::IUniswapV2Pair::IUniswapV2PairEvents::SIGNATURES.clone();
-    let v4_events = sol_types::StateView::StateViewEvents::SIGNATURES.clone();
-    print!("==v4 events: {:?}", &v4_events);
-    [v2_events, v3_events, v4_events].concat()
-}
+    #[test]
+    fn decode_failures_are_counted() {
+        let decoder = EventDecoder::<UnifiedPoolEvent>::default();
+        let topic0 = sol_types::IUniswapV2Pair::Sync::SIGNATURE_HASH;
+        let truncated = Log {
+            inner: alloy::primitives::Log {
+                address: Address::ZERO,
+                data: LogData::new_unchecked(vec![topic0], Default::default()),
+            },
+            ..Default::default()
+        };
 
-#[derive(Debug, Clone)]
-pub enum UnifiedPoolEvent {
-    // UNISWAP V2
-    V2Mint(),
-    V2Burn(),
-    V2Swap(),
-    V2Sync(),
-    V2Approval(),
-    V2Transfer(),
-
-    // UNISWAP V3
-    V3Mint(),
-    V3Swap(),
-    V3Collect(),
-    V3Burn(),
-    V3Flash(),
-
-    // UNISWAP V4 STATEVIEW
-    V4Donate(),
-    V4Initialize(),
-    V4Modify(),
-    V4Swap(),
-}
+        assert!(decoder.decode(&truncated).is_none());
+        assert!(decoder.decode(&truncated).is_none());
+        assert_eq!(decoder.failures(), vec![(topic0, 2)]);
+    }
 
-#[derive(Debug, Clone)]
-pub enum UnifiedPoolEventResponse {
-    // UNISWAP V2
-    V2Mint(Log<sol_types::IUniswapV2Pair::Mint>),
-    V2Burn(Log<sol_types::IUniswapV2Pair::Burn>),
-    V2Swap(Log<sol_types::IUniswapV2Pair::Swap>),
-    V2Sync(Log<sol_types::IUniswapV2Pair::Sync>),
-    V2Approval(Log<sol_types::IUniswapV2Pair::Approval>),
-    V2Transfer(Log<sol_types::IUniswapV2Pair::Transfer>),
-
-    // UNISWAP V3
-    V3Mint(Log<sol_types::V3Pool::Mint>),
-    V3Swap(Log<sol_types::V3Pool::Swap>),
-    V3Collect(Log<sol_types::V3Pool::Collect>),
-    V3Burn(Log<sol_types::V3Pool::Burn>),
-    V3Flash(Log<sol_types::V3Pool::Flash>),
-
-    // UNISWAP V4 STATEVIEW
-    V4Donate(Log<IPoolManager::Donate>),
-    V4Initialize(Log<IPoolManager::Initialize>),
-    V4Modify(Log<IPoolManager::ModifyLiquidity>),
-    V4Swap(Log<IPoolManager::Swap>),
-}
+    #[test]
+    fn logs_of_other_registries_are_not_failures() {
+        let decoder = EventDecoder::<UnifiedPoolEvent>::default();
+        let tokens = EventDecoder::<UnifiedTokenEvent>::default();
+        // same topic0 as the pair `Transfer`, but the token id is indexed
+        let nft = sol_types::IERC721::Transfer {
+            from: Address::repeat_byte(1),
+            to: Address::repeat_byte(2),
+            tokenId: alloy::primitives::U256::from(7),
+        };
+        let log = Log {
+            inner: alloy::primitives::Log {
+                address: Address::ZERO,
+                data: nft.encode_log_data(),
+            },
+            ..Default::default()
+        };
 
-impl UnifiedPoolEventResponse {
-    pub fn handle(&self, ctx: &MasterContext, chain_id: u64) {
-        match self {
-            UnifiedPoolEventResponse::V2Mint(log) => {}
-            UnifiedPoolEventResponse::V2Burn(log) => todo!(),
-            UnifiedPoolEventResponse::V2Swap(log) => ctx.handle_v2_swap(log.to_owned(), chain_id),
-            UnifiedPoolEventResponse::V2Sync(log) => todo!(),
-            UnifiedPoolEventResponse::V2Approval(log) => todo!(),
-            UnifiedPoolEventResponse::V2Transfer(log) => todo!(),
-            UnifiedPoolEventResponse::V3Mint(log) => todo!(),
-            UnifiedPoolEventResponse::V3Swap(log) => ctx.handle_v3_swap(log.to_owned(), chain_id),
-            UnifiedPoolEventResponse::V3Collect(log) => todo!(),
-            UnifiedPoolEventResponse::V3Burn(log) => todo!(),
-            UnifiedPoolEventResponse::V3Flash(log) => todo!(),
-            UnifiedPoolEventResponse::V4Donate(log) => todo!(),
-            UnifiedPoolEventResponse::V4Initialize(log) => todo!(),
-            UnifiedPoolEventResponse::V4Modify(log) => todo!(),
-            UnifiedPoolEventResponse::V4Swap(log) => todo!(),
-        }
+        assert!(decoder.decode_or_defer(&log, &tokens).is_none());
+        assert!(decoder.failures().is_empty());
+        assert!(decoder.decode(&log).is_none());
+        assert_eq!(decoder.failures().len(), 1);
     }
 }
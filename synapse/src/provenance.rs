@@ -0,0 +1,77 @@
+use all_sol_types::sol_types::{
+    IUniswapV2Factory::IUniswapV2FactoryInstance, IUniswapV3Factory::IUniswapV3FactoryInstance,
+};
+use alloy::primitives::{Address, aliases::U24};
+use shape::{
+    d_any::PoolProvenance,
+    p_config::{V2Config, V3Config},
+};
+
+use crate::{WsProvider, master_context::MasterContext};
+
+/// Checks a pair against the factory it claims, through CREATE2 derivation when the
+/// init code hash of the dex is known and `getPair` otherwise. `None` when the factory
+/// call fails and the pool can't be judged yet
+pub async fn verify_v2(
+    ctx: &MasterContext,
+    chain_id: u64,
+    provider: WsProvider,
+    pool: Address,
+    factory: Address,
+    token0: Address,
+    token1: Address,
+) -> Option<PoolProvenance> {
+    let Some(dex) = ctx.v2_dex(chain_id, factory) else {
+        return Some(PoolProvenance::UnknownDex);
+    };
+    if let Some(init_code_hash) = dex.v2_init_code_hash() {
+        let config = V2Config {
+            name: "".to_string(),
+            fee: U24::ZERO,
+            token0,
+            token1,
+        };
+        return Some(judge(config.pool_address(factory, init_code_hash), pool));
+    }
+
+    let pair = IUniswapV2FactoryInstance::new(factory, provider)
+        .getPair(token0, token1)
+        .call()
+        .await
+        .ok()?;
+    Some(judge(pair, pool))
+}
+
+/// Checks a pool against the factory it claims, through CREATE2 derivation when the
+/// init code hash of the dex is known and `getPool` otherwise. The tokens and fee the
+/// pool is checked for come from `config`
+pub async fn verify_v3(
+    ctx: &MasterContext,
+    chain_id: u64,
+    provider: WsProvider,
+    pool: Address,
+    factory: Address,
+    config: &V3Config,
+) -> Option<PoolProvenance> {
+    let Some(dex) = ctx.v3_dex(chain_id, factory) else {
+        return Some(PoolProvenance::UnknownDex);
+    };
+    if let Some(init_code_hash) = dex.v3_init_code_hash() {
+        return Some(judge(config.pool_address(factory, init_code_hash), pool));
+    }
+
+    let registered = IUniswapV3FactoryInstance::new(factory, provider)
+        .getPool(config.token0, config.token1, config.fee)
+        .call()
+        .await
+        .ok()?;
+    Some(judge(registered, pool))
+}
+
+fn judge(registered: Address, pool: Address) -> PoolProvenance {
+    if registered == pool {
+        PoolProvenance::Trusted
+    } else {
+        PoolProvenance::Untrusted
+    }
+}
@@ -0,0 +1,454 @@
+use std::{collections::BTreeSet, future::Future};
+
+use alloy::{
+    primitives::{Address, B256, aliases::I24},
+    providers::Provider,
+};
+use shape::p_ticks::{PoolWords, TicksBitMap};
+use v3::v3_base::{
+    bitmap_math::{self, word_bounds},
+    err::TradeError,
+    states::TradeOutcome,
+    ticks::Tick,
+    trade_math,
+    v3_state::V3State,
+};
+
+use crate::calls::{MulticallBatcher, get_v3_words, get_v4_words};
+
+/// How many bitmap words around the current tick make up the window of a pool
+#[derive(Debug, Clone, Copy)]
+pub enum WindowSize {
+    /// this many words on each side of the word holding the current tick
+    Fixed(i16),
+    /// words are added on each side until the liquidity that can be crossed on it adds
+    /// up to `target`, or `max_words` words were loaded on that side
+    Depth { target: u128, max_words: i16 },
+}
+
+/// Decides which tick bitmap words of a pool get loaded
+#[derive(Debug, Clone, Copy)]
+pub struct TickWindow {
+    pub size: WindowSize,
+    /// times a simulation may load the words it is missing past the window
+    pub max_extensions: usize,
+}
+
+impl Default for TickWindow {
+    fn default() -> Self {
+        Self {
+            size: WindowSize::Fixed(2),
+            max_extensions: 8,
+        }
+    }
+}
+
+/// Reads the tick bitmap words of one pool
+pub trait WordSource {
+    fn tick_spacing(&self) -> I24;
+
+    fn load(&self, positions: &[i16]) -> impl Future<Output = Vec<Option<TicksBitMap>>> + Send;
+}
+
+pub struct V3Words<P> {
+    pub batcher: MulticallBatcher<P>,
+    pub pool: Address,
+    pub tick_spacing: I24,
+}
+
+impl<P: Provider + Clone> WordSource for V3Words<P> {
+    fn tick_spacing(&self) -> I24 {
+        self.tick_spacing
+    }
+
+    fn load(&self, positions: &[i16]) -> impl Future<Output = Vec<Option<TicksBitMap>>> + Send {
+        let words: Vec<(Address, i16, I24)> = positions
+            .iter()
+            .map(|pos| (self.pool, *pos, self.tick_spacing))
+            .collect();
+        async move { get_v3_words(&self.batcher, &words).await }
+    }
+}
+
+pub struct V4Words<P> {
+    pub batcher: MulticallBatcher<P>,
+    pub state_view: Address,
+    pub id: B256,
+    pub tick_spacing: I24,
+}
+
+impl<P: Provider + Clone> WordSource for V4Words<P> {
+    fn tick_spacing(&self) -> I24 {
+        self.tick_spacing
+    }
+
+    fn load(&self, positions: &[i16]) -> impl Future<Output = Vec<Option<TicksBitMap>>> + Send {
+        let words: Vec<(B256, i16, I24)> = positions
+            .iter()
+            .map(|pos| (self.id, *pos, self.tick_spacing))
+            .collect();
+        async move { get_v4_words(&self.batcher, self.state_view, &words).await }
+    }
+}
+
+impl TickWindow {
+    /// Words loaded first around `tick`, a `Depth` window starts with the words next to
+    /// the current one and grows from there
+    pub fn initial_positions(&self, tick: I24, tick_spacing: I24) -> Vec<i16> {
+        let center = bitmap_math::get_pos_from_tick(tick, tick_spacing);
+        let side = match self.size {
+            WindowSize::Fixed(words) => words.max(0),
+            WindowSize::Depth { .. } => 1,
+        };
+        let (min, max) = word_bounds(tick_spacing);
+        (-side..=side)
+            .filter_map(|offset| center.checked_add(offset))
+            .filter(|pos| (min..=max).contains(pos))
+            .collect()
+    }
+
+    /// Loads the window of a pool, `None` unless every word of it could be read
+    pub async fn load<S: WordSource>(&self, source: &S, tick: I24) -> Option<PoolWords> {
+        let positions = self.initial_positions(tick, source.tick_spacing());
+        let loaded = source.load(&positions).await;
+
+        let mut words = PoolWords::default();
+        for (pos, word) in positions.into_iter().zip(loaded) {
+            // a missing word would make the simulator see an empty range, so the
+            // window is only complete once every word is there
+            words.words.insert(pos, word?);
+        }
+        self.deepen(source, &mut words, tick).await;
+        Some(words)
+    }
+
+    /// Grows a `Depth` window by one word per side and round until both sides hold the
+    /// target liquidity, a side stops growing at the first word that can't be read
+    pub async fn deepen<S: WordSource>(&self, source: &S, words: &mut PoolWords, tick: I24) {
+        let WindowSize::Depth { target, max_words } = self.size else {
+            return;
+        };
+        let tick_spacing = source.tick_spacing();
+        let center = bitmap_math::get_pos_from_tick(tick, tick_spacing);
+        let mut open = [true, true];
+
+        loop {
+            let mut positions = Vec::new();
+            for (side, up) in [(0, true), (1, false)] {
+                if !open[side] || side_depth(words, tick, up) >= target {
+                    open[side] = false;
+                    continue;
+                }
+                match next_position(words, up, tick_spacing) {
+                    Some(pos) if (pos as i32 - center as i32).abs() <= max_words as i32 => {
+                        positions.push((side, pos))
+                    }
+                    _ => open[side] = false,
+                }
+            }
+            if positions.is_empty() {
+                return;
+            }
+
+            let loaded = source
+                .load(&positions.iter().map(|(_, pos)| *pos).collect::<Vec<_>>())
+                .await;
+            for ((side, pos), word) in positions.into_iter().zip(loaded) {
+                match word {
+                    Some(word) => {
+                        words.words.insert(pos, word);
+                    }
+                    None => open[side] = false,
+                }
+            }
+        }
+    }
+
+    /// Loads the window words around `tick` that `words` doesn't hold yet, then deepens
+    /// it from there. Cached words stay where the pool was hydrated while swaps move the
+    /// tick, this brings them back around it. Returns the positions added
+    pub async fn recenter<S: WordSource>(
+        &self,
+        source: &S,
+        words: &mut PoolWords,
+        tick: I24,
+    ) -> Vec<i16> {
+        let before: BTreeSet<i16> = words.words.keys().copied().collect();
+        let positions: Vec<i16> = self
+            .initial_positions(tick, source.tick_spacing())
+            .into_iter()
+            .filter(|pos| !before.contains(pos))
+            .collect();
+        if !positions.is_empty() {
+            let loaded = source.load(&positions).await;
+            for (pos, word) in positions.into_iter().zip(loaded) {
+                if let Some(word) = word {
+                    words.words.insert(pos, word);
+                }
+            }
+        }
+        self.deepen(source, words, tick).await;
+        words
+            .words
+            .keys()
+            .filter(|pos| !before.contains(pos))
+            .copied()
+            .collect()
+    }
+
+    /// Resumes a trade on `pool` each time it stopped at words that aren't loaded, up
+    /// to `max_extensions` times. The words are read from `source`, added to `pool` and
+    /// handed to `merge`, a word that can't be read ends the trade where it stopped
+    pub async fn extend<S: WordSource>(
+        &self,
+        source: &S,
+        pool: &mut V3State,
+        mut outcome: Result<TradeOutcome, TradeError>,
+        mut merge: impl FnMut(i16, TicksBitMap),
+    ) -> Result<TradeOutcome, TradeError> {
+        for _ in 0..self.max_extensions {
+            let (state, missing_words) = match &outcome {
+                Ok(TradeOutcome::Partial {
+                    state,
+                    missing_words,
+                }) if !missing_words.is_empty() => (*state, missing_words.clone()),
+                _ => break,
+            };
+
+            let loaded = source.load(&missing_words).await;
+            let mut ticks = Vec::new();
+            for (pos, word) in missing_words.into_iter().zip(loaded) {
+                let Some(word) = word else {
+                    return outcome;
+                };
+                ticks.extend(word.ticks.iter().map(|(tick, data)| Tick {
+                    tick: *tick,
+                    liquidity_net: data.liquidity_net,
+                }));
+                pool.words.insert(pos);
+                merge(pos, word);
+            }
+            pool.ticks.insert_ticks(ticks);
+            outcome = trade_math::retry(state, pool);
+        }
+        outcome
+    }
+}
+
+/// Word right past the upper (`up`) or lower edge of the loaded words
+fn next_position(words: &PoolWords, up: bool, tick_spacing: I24) -> Option<i16> {
+    let (min, max) = word_bounds(tick_spacing);
+    let next = if up {
+        words.words.keys().next_back()?.checked_add(1)?
+    } else {
+        words.words.keys().next()?.checked_sub(1)?
+    };
+    (min..=max).contains(&next).then_some(next)
+}
+
+/// Liquidity that can be crossed above (`up`) or below `tick` within the loaded words
+fn side_depth(words: &PoolWords, tick: I24, up: bool) -> u128 {
+    words
+        .words
+        .values()
+        .flat_map(|w| w.ticks.iter())
+        .filter(|(t, _)| if up { **t > tick } else { **t <= tick })
+        .map(|(_, data)| data.liquidity_net.unwrap_or(0).unsigned_abs())
+        .fold(0, u128::saturating_add)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, sync::Mutex};
+
+    use alloy::primitives::{U256, aliases::U24};
+    use shape::p_ticks::TickData;
+    use v3::v3_base::{tick_math::MAX_TICK, ticks::Ticks};
+
+    use super::*;
+
+    /// Words of a pool held in memory, positions it doesn't have can't be read
+    struct Words {
+        tick_spacing: I24,
+        words: BTreeMap<i16, TicksBitMap>,
+        loads: Mutex<Vec<Vec<i16>>>,
+    }
+
+    impl Words {
+        fn new(tick_spacing: i32, words: &[(i16, TicksBitMap)]) -> Self {
+            Self {
+                tick_spacing: I24::try_from(tick_spacing).unwrap(),
+                words: words.iter().cloned().collect(),
+                loads: Mutex::new(Vec::new()),
+            }
+        }
+    }
+
+    impl WordSource for Words {
+        fn tick_spacing(&self) -> I24 {
+            self.tick_spacing
+        }
+
+        fn load(&self, positions: &[i16]) -> impl Future<Output = Vec<Option<TicksBitMap>>> + Send {
+            self.loads.lock().unwrap().push(positions.to_vec());
+            let words = positions.iter().map(|p| self.words.get(p).cloned());
+            std::future::ready(words.collect())
+        }
+    }
+
+    fn word(ticks: &[(i32, i128)]) -> TicksBitMap {
+        TicksBitMap {
+            bitmap: U256::ZERO,
+            ticks: ticks
+                .iter()
+                .map(|(tick, net)| {
+                    (
+                        I24::try_from(*tick).unwrap(),
+                        TickData {
+                            liquidity_net: Some(*net),
+                            liquidity_gross: Some(net.unsigned_abs()),
+                        },
+                    )
+                })
+                .collect::<BTreeMap<_, _>>(),
+        }
+    }
+
+    #[test]
+    fn fixed_window_is_clamped_to_the_tick_range() {
+        let spacing = I24::try_from(60).unwrap();
+        let window = TickWindow::default();
+        assert_eq!(
+            window.initial_positions(I24::ZERO, spacing),
+            vec![-2, -1, 0, 1, 2]
+        );
+
+        let (_, max) = word_bounds(spacing);
+        let top = window.initial_positions(I24::try_from(MAX_TICK - 1).unwrap(), spacing);
+        assert_eq!(top, vec![max - 2, max - 1, max]);
+    }
+
+    #[test]
+    fn depth_and_edges_of_loaded_words() {
+        let spacing = I24::try_from(10).unwrap();
+        let mut words = PoolWords::default();
+        words.words.insert(0, word(&[(10, 100), (50, -40)]));
+        words.words.insert(-1, word(&[(-20, 7)]));
+
+        let tick = I24::try_from(15).unwrap();
+        assert_eq!(side_depth(&words, tick, true), 40);
+        assert_eq!(side_depth(&words, tick, false), 107);
+
+        assert_eq!(next_position(&words, true, spacing), Some(1));
+        assert_eq!(next_position(&words, false, spacing), Some(-2));
+    }
+
+    #[tokio::test]
+    async fn depth_window_grows_until_the_target_or_an_unreadable_word() {
+        let source = Words::new(
+            10,
+            &[
+                (-1, word(&[(-20, 7)])),
+                (0, word(&[(10, 100), (50, -40)])),
+                (1, word(&[])),
+                (2, word(&[(5200, -120)])),
+                (-2, word(&[])),
+            ],
+        );
+        let window = TickWindow {
+            size: WindowSize::Depth {
+                target: 150,
+                max_words: 3,
+            },
+            max_extensions: 0,
+        };
+
+        let words = window
+            .load(&source, I24::try_from(15).unwrap())
+            .await
+            .unwrap();
+        // above, 40 is short of the target until word 2 adds 120, below word -3 can't
+        // be read so the side stops short at 107
+        assert_eq!(
+            words.words.keys().copied().collect::<Vec<_>>(),
+            vec![-2, -1, 0, 1, 2]
+        );
+        assert_eq!(
+            *source.loads.lock().unwrap(),
+            vec![vec![-1, 0, 1], vec![2, -2], vec![-3]]
+        );
+    }
+
+    #[tokio::test]
+    async fn recenter_loads_the_window_around_the_moved_tick() {
+        let source = Words::new(60, &[(2, word(&[])), (3, word(&[])), (4, word(&[]))]);
+        let mut words = PoolWords::default();
+        for pos in [-1, 0, 1] {
+            words.words.insert(pos, word(&[]));
+        }
+        let window = TickWindow {
+            size: WindowSize::Fixed(1),
+            max_extensions: 0,
+        };
+
+        let tick = I24::try_from(60 * 256 * 3).unwrap();
+        let added = window.recenter(&source, &mut words, tick).await;
+        assert_eq!(added, vec![2, 3, 4]);
+        assert_eq!(words.words.len(), 6);
+        // nothing is read again once the window is around the tick
+        assert!(window.recenter(&source, &mut words, tick).await.is_empty());
+        assert_eq!(source.loads.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn partial_trades_load_the_missing_words_and_resume() {
+        let net = 10i128.pow(18);
+        let deep = 10i128.pow(22);
+        let source = Words::new(60, &[(-2, word(&[(-15480, deep), (-15420, -deep)]))]);
+        let pool = || V3State {
+            tick: I24::ZERO,
+            ticks: Ticks::new(vec![
+                Tick {
+                    tick: I24::try_from(-600).unwrap(),
+                    liquidity_net: Some(net),
+                },
+                Tick {
+                    tick: I24::try_from(600).unwrap(),
+                    liquidity_net: Some(-net),
+                },
+            ]),
+            liquidity: U256::from(net),
+            x96price: U256::ONE << 96,
+            tick_spacing: I24::try_from(60).unwrap(),
+            words: BTreeSet::from([-1, 0]),
+        };
+        let fee = U24::from(3000);
+        let amount_in = U256::from(10u64.pow(18));
+        let window = TickWindow::default();
+
+        let mut sim = pool();
+        let outcome = trade_math::trade(&sim, &fee, amount_in, true, None);
+        assert!(matches!(outcome, Ok(TradeOutcome::Partial { .. })));
+        let mut merged = Vec::new();
+        let outcome = window
+            .extend(&source, &mut sim, outcome, |pos, _| merged.push(pos))
+            .await;
+        let Ok(TradeOutcome::Filled(state)) = outcome else {
+            panic!("expected the trade to fill once word -2 was loaded");
+        };
+        assert_eq!(state.remaining, U256::ZERO);
+        assert_eq!(state.liquidity, U256::from(deep.unsigned_abs()));
+        assert_eq!(merged, vec![-2]);
+        assert!(sim.words.contains(&-2));
+
+        // without extensions the trade stays where it ran out of ticks
+        let none = TickWindow {
+            max_extensions: 0,
+            ..window
+        };
+        let mut sim = pool();
+        let outcome = trade_math::trade(&sim, &fee, amount_in, true, None);
+        let outcome = none.extend(&source, &mut sim, outcome, |_, _| {}).await;
+        assert!(matches!(outcome, Ok(TradeOutcome::Partial { .. })));
+    }
+}
@@ -2,7 +2,7 @@ use std::hash::Hash;
 
 use alloy::{
     primitives::{
-        Address, B256, FixedBytes,
+        Address, B256, FixedBytes, I256, U256,
         map::{HashMap, HashSet},
     },
     providers::{Provider, ProviderBuilder},
@@ -14,7 +14,11 @@ use alloy::{
     transports::{RpcError, TransportErrorKind, http::reqwest::Url, ws::WsConnect},
 };
 
+use dashmap::DashMap;
 use futures::channel::mpsc::UnboundedReceiver;
+use shape::id_address::IdAddress;
+
+use crate::event_registry::{EventDecoder, EventRegistry, event_registry};
 
 // Ajuste os imports abaixo conforme os tipos reais no seu crate `sol::sol_types`.
 // Estou seguindo a mesma convenção do seu exemplo.
@@ -38,106 +42,252 @@ pub struct Chunk {
     id: u32,
 }
 
-pub struct TokenEvents {
-    map: HashMap<FixedBytes<32>, UnifiedTokenEvent>,
-}
-
-/// Gera um HashMap que associa signature_hash -> UnifiedTokenEvent
-pub fn generate_tokens_events_map() -> std::collections::HashMap<FixedBytes<32>, UnifiedTokenEvent>
-{
-    let mut map = HashMap::new();
-
-    // ERC-20
-    let erc20_transfer_hash = ERC20Transfer::SIGNATURE_HASH;
-    map.insert(erc20_transfer_hash, UnifiedTokenEvent::ERC20Transfer());
-
-    let erc20_approval_hash = ERC20Approval::SIGNATURE_HASH;
-    map.insert(erc20_approval_hash, UnifiedTokenEvent::ERC20Approval());
-
-    // ERC-721
-    let erc721_transfer_hash = ERC721Transfer::SIGNATURE_HASH;
-    map.insert(erc721_transfer_hash, UnifiedTokenEvent::ERC721Transfer());
-
-    let erc721_approval_hash = ERC721Approval::SIGNATURE_HASH;
-    map.insert(erc721_approval_hash, UnifiedTokenEvent::ERC721Approval());
-
-    let erc721_approval_for_all_hash = ERC721ApprovalForAll::SIGNATURE_HASH;
-    map.insert(
-        erc721_approval_for_all_hash,
-        UnifiedTokenEvent::ERC721ApprovalForAll(),
-    );
-
-    // ERC-1155
-    let erc1155_transfer_single_hash = ERC1155TransferSingle::SIGNATURE_HASH;
-    map.insert(
-        erc1155_transfer_single_hash,
-        UnifiedTokenEvent::ERC1155TransferSingle(),
-    );
-
-    let erc1155_transfer_batch_hash = ERC1155TransferBatch::SIGNATURE_HASH;
-    map.insert(
-        erc1155_transfer_batch_hash,
-        UnifiedTokenEvent::ERC1155TransferBatch(),
-    );
-
-    let erc1155_approval_for_all_hash = ERC1155ApprovalForAll::SIGNATURE_HASH;
-    map.insert(
-        erc1155_approval_for_all_hash,
-        UnifiedTokenEvent::ERC1155ApprovalForAll(),
-    );
-
-    // Se quiser, adicionar variantes para Mint/Burn custom (muitos tokens usam Transfer with zero address)
-    // let token_mint_hash = MyToken::Mint::SIGNATURE_HASH;
-    // map.insert(token_mint_hash, UnifiedTokenEvent::TokenMint());
-
-    map
+event_registry! {
+    /// Token events, erc20 and erc721 share the `Transfer`/`Approval` topic0 and are
+    /// told apart by their indexed topics when decoding
+    pub enum UnifiedTokenEvent => UnifiedTokenEventResponse {
+        // ERC-20
+        ERC20Transfer => ERC20Transfer,
+        ERC20Approval => ERC20Approval,
+        // ERC-721
+        ERC721Transfer => ERC721Transfer,
+        ERC721Approval => ERC721Approval,
+        ERC721ApprovalForAll => ERC721ApprovalForAll,
+        // ERC-1155
+        ERC1155TransferSingle => ERC1155TransferSingle,
+        ERC1155TransferBatch => ERC1155TransferBatch,
+        ERC1155ApprovalForAll => ERC1155ApprovalForAll,
+    }
 }
 
 /// Retorna a concatenação das signatures conhecidas (útil pra `Filter::topics` etc)
 pub fn generate_token_events() -> Vec<&'static str> {
-    let erc20_events = IERC20Events::SIGNATURES.clone();
-    let erc721_events = IERC721Events::SIGNATURES.clone();
-    let erc1155_events = IERC1155Events::SIGNATURES.clone();
+    UnifiedTokenEvent::signatures()
+}
 
-    [erc20_events, erc721_events, erc1155_events].concat()
+/// Transfer counters of a token since the tracker started
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TokenCounters {
+    pub transfers: u64,
+    pub mints: u64,
+    pub burns: u64,
+    pub approvals: u64,
 }
 
-#[derive(Debug, Clone)]
-pub enum UnifiedTokenEvent {
-    // ERC-20
-    ERC20Transfer(),
-    ERC20Approval(),
-
-    // ERC-721
-    ERC721Transfer(),
-    ERC721Approval(),
-    ERC721ApprovalForAll(),
-
-    // ERC-1155
-    ERC1155TransferSingle(),
-    ERC1155TransferBatch(),
-    ERC1155ApprovalForAll(),
-    // Opcional:
-    // TokenMint(),
-    // TokenBurn(),
+/// Balance of `holder` within a token moved by the transfers seen, `id` is only set
+/// for erc1155
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct HolderKey {
+    pub id: Option<U256>,
+    pub holder: Address,
 }
 
-#[derive(Debug, Clone)]
-pub enum UnifiedTokenEventResponse {
-    // ERC-20
-    ERC20Transfer(Log<ERC20Transfer>),
-    ERC20Approval(Log<ERC20Approval>),
-
-    // ERC-721
-    ERC721Transfer(Log<ERC721Transfer>),
-    ERC721Approval(Log<ERC721Approval>),
-    ERC721ApprovalForAll(Log<ERC721ApprovalForAll>),
-    // ERC-1155
-    // these dont impl debug in the sol crate
-    // ERC1155TransferSingle(Log<ERC1155TransferSingle>),
-    // ERC1155TransferBatch(Log<ERC1155TransferBatch>),
-    // ERC1155ApprovalForAll(Log<ERC1155ApprovalForAll>),
-    // Opcional: Mint/Burn se você declarar tipos específicos para eles
-    // TokenMint(Log<MyToken::Mint>),
-    // TokenBurn(Log<MyToken::Burn>),
+/// In memory counters and holder balance deltas of every token whose events reach the
+/// listeners. Erc721 deltas count tokens held, erc1155 deltas are kept per id.
+#[derive(Default)]
+pub struct TokenTracker {
+    decoder: EventDecoder<UnifiedTokenEvent>,
+    counters: DashMap<IdAddress, TokenCounters>,
+    /// holder balance deltas per token
+    balances: DashMap<IdAddress, HashMap<HolderKey, I256>>,
+}
+
+impl TokenTracker {
+    /// Applies a raw log, a log the node marked as `removed` is applied in reverse
+    pub fn handle_log(&self, chain_id: u64, log: &Log) {
+        let Some(event) = self.decoder.decode(log) else {
+            return;
+        };
+        let token = IdAddress {
+            id: chain_id,
+            address: log.address(),
+        };
+        let sign = if log.removed { -1 } else { 1 };
+
+        match event {
+            UnifiedTokenEventResponse::ERC20Transfer(l) => {
+                let e = l.inner.data;
+                self.transfer(&token, e.from, e.to, None, e.value, sign);
+            }
+            UnifiedTokenEventResponse::ERC721Transfer(l) => {
+                let e = l.inner.data;
+                self.transfer(&token, e.from, e.to, None, U256::from(1), sign);
+            }
+            UnifiedTokenEventResponse::ERC1155TransferSingle(l) => {
+                let e = l.inner.data;
+                self.transfer(&token, e.from, e.to, Some(e.id), e.value, sign);
+            }
+            UnifiedTokenEventResponse::ERC1155TransferBatch(l) => {
+                let e = l.inner.data;
+                for (id, value) in e.ids.into_iter().zip(e.values) {
+                    self.transfer(&token, e.from, e.to, Some(id), value, sign);
+                }
+            }
+            UnifiedTokenEventResponse::ERC20Approval(_)
+            | UnifiedTokenEventResponse::ERC721Approval(_)
+            | UnifiedTokenEventResponse::ERC721ApprovalForAll(_)
+            | UnifiedTokenEventResponse::ERC1155ApprovalForAll(_) => {
+                let mut counters = self.counters.entry(token).or_default();
+                counters.approvals = step(counters.approvals, sign);
+            }
+        }
+    }
+
+    /// Transfers from the zero address are mints, transfers to it are burns
+    fn transfer(
+        &self,
+        token: &IdAddress,
+        from: Address,
+        to: Address,
+        id: Option<U256>,
+        amount: U256,
+        sign: i64,
+    ) {
+        {
+            let mut counters = self.counters.entry(token.clone()).or_default();
+            counters.transfers = step(counters.transfers, sign);
+            if from == Address::ZERO {
+                counters.mints = step(counters.mints, sign);
+            }
+            if to == Address::ZERO {
+                counters.burns = step(counters.burns, sign);
+            }
+        }
+
+        let amount = I256::try_from(amount).unwrap_or(I256::MAX);
+        let amount = if sign < 0 { -amount } else { amount };
+        let mut balances = self.balances.entry(token.clone()).or_default();
+        for (holder, delta) in [(from, -amount), (to, amount)] {
+            if holder == Address::ZERO || delta.is_zero() {
+                continue;
+            }
+            let balance = balances.entry(HolderKey { id, holder }).or_default();
+            *balance = balance.saturating_add(delta);
+        }
+    }
+
+    pub fn counters(&self, token: &IdAddress) -> Option<TokenCounters> {
+        self.counters.get(token).map(|c| c.clone())
+    }
+
+    /// Holder balance deltas of a token, erc1155 holders appear once per id
+    pub fn balance_deltas(&self, token: &IdAddress) -> Vec<(Address, Option<U256>, I256)> {
+        self.balances
+            .get(token)
+            .map(|balances| {
+                balances
+                    .iter()
+                    .map(|(key, delta)| (key.holder, key.id, *delta))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn decode_failures(&self) -> Vec<(B256, u64)> {
+        self.decoder.failures()
+    }
+
+    pub fn decoder(&self) -> &EventDecoder<UnifiedTokenEvent> {
+        &self.decoder
+    }
+}
+
+fn step(counter: u64, sign: i64) -> u64 {
+    counter.saturating_add_signed(sign)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn raw_log<E: SolEvent>(event: &E) -> Log {
+        Log {
+            inner: alloy::primitives::Log {
+                address: Address::ZERO,
+                data: event.encode_log_data(),
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn shared_topic0_decodes_by_indexed_layout() {
+        let decoder = EventDecoder::<UnifiedTokenEvent>::default();
+        let from = Address::repeat_byte(1);
+        let to = Address::repeat_byte(2);
+
+        let erc20 = raw_log(&ERC20Transfer {
+            from,
+            to,
+            value: U256::from(10),
+        });
+        let erc721 = raw_log(&ERC721Transfer {
+            from,
+            to,
+            tokenId: U256::from(10),
+        });
+
+        assert!(matches!(
+            decoder.decode(&erc20),
+            Some(UnifiedTokenEventResponse::ERC20Transfer(_))
+        ));
+        assert!(matches!(
+            decoder.decode(&erc721),
+            Some(UnifiedTokenEventResponse::ERC721Transfer(_))
+        ));
+        assert!(decoder.failures().is_empty());
+    }
+
+    #[test]
+    fn tracks_mints_burns_and_holder_deltas() {
+        let tracker = TokenTracker::default();
+        let token = IdAddress {
+            id: 1,
+            address: Address::ZERO,
+        };
+        let alice = Address::repeat_byte(1);
+        let bob = Address::repeat_byte(2);
+        let transfer = |from, to, value: u64| {
+            raw_log(&ERC20Transfer {
+                from,
+                to,
+                value: U256::from(value),
+            })
+        };
+
+        tracker.handle_log(1, &transfer(Address::ZERO, alice, 100));
+        tracker.handle_log(1, &transfer(alice, bob, 30));
+        tracker.handle_log(1, &transfer(bob, Address::ZERO, 10));
+
+        let mut removed = transfer(alice, bob, 5);
+        tracker.handle_log(1, &transfer(alice, bob, 5));
+        removed.removed = true;
+        tracker.handle_log(1, &removed);
+
+        assert_eq!(
+            tracker.counters(&token),
+            Some(TokenCounters {
+                transfers: 3,
+                mints: 1,
+                burns: 1,
+                approvals: 0,
+            })
+        );
+
+        let mut deltas = tracker.balance_deltas(&token);
+        deltas.sort();
+        assert_eq!(
+            deltas,
+            vec![
+                (alice, None, I256::try_from(70).unwrap()),
+                (bob, None, I256::try_from(20).unwrap()),
+            ]
+        );
+
+        let other = IdAddress {
+            id: 2,
+            address: Address::ZERO,
+        };
+        assert!(tracker.balance_deltas(&other).is_empty());
+    }
 }
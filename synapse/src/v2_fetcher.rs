@@ -0,0 +1,116 @@
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use alloy::primitives::{Address, aliases::U24};
+use futures::future::join_all;
+use shape::{id_address::IdAddress, p_config::V2Config, p_state::V2State};
+
+use crate::{
+    calls::{
+        DEFAULT_MULTICALL_BATCH, MulticallBatcher, get_sync_point, get_v2_pools, pinned_block,
+    },
+    master_context::MasterContext,
+    provenance::verify_v2,
+};
+
+/// fee used for pairs whose factory is not one of the configured dexes
+const DEFAULT_V2_FEE: u32 = 3000;
+
+/// Drains `MasterContext::v2_reserves_queue`, fetching config and reserves of the queued
+/// pairs in batches per chain
+pub struct V2Fetcher {
+    pub batch_size: usize,
+    pub interval: Duration,
+    /// calls per Multicall3 request
+    pub multicall_batch: usize,
+}
+
+impl Default for V2Fetcher {
+    fn default() -> Self {
+        Self {
+            batch_size: 500,
+            interval: Duration::from_secs(1),
+            multicall_batch: DEFAULT_MULTICALL_BATCH,
+        }
+    }
+}
+
+impl V2Fetcher {
+    pub async fn run(self, ctx: Arc<MasterContext>) {
+        loop {
+            let queued = ctx.take_v2_queue();
+            if queued.is_empty() {
+                tokio::time::sleep(self.interval).await;
+                continue;
+            }
+
+            let mut by_chain = BTreeMap::<u64, Vec<IdAddress>>::new();
+            for key in queued {
+                by_chain.entry(key.id).or_default().push(key);
+            }
+
+            for (chain_id, pools) in by_chain {
+                self.fetch_chain(&ctx, chain_id, pools).await;
+            }
+        }
+    }
+
+    async fn fetch_chain(&self, ctx: &MasterContext, chain_id: u64, pools: Vec<IdAddress>) {
+        let Some(provider) = ctx.provider(chain_id) else {
+            println!("no provider for chain {}, requeueing v2 pools", chain_id);
+            pools.into_iter().for_each(|k| ctx.enqueue_v2(k));
+            return;
+        };
+
+        for batch in pools.chunks(self.batch_size.max(1)) {
+            let Some(synced_at) = get_sync_point(&provider).await else {
+                println!("chain {} head lookup failed, requeueing v2 pools", chain_id);
+                batch.iter().for_each(|k| ctx.enqueue_v2(k.clone()));
+                continue;
+            };
+            let batcher = MulticallBatcher::new(provider.clone())
+                .batch_size(self.multicall_batch)
+                .at_block(pinned_block(&synced_at));
+            let addresses: Vec<Address> = batch.iter().map(|k| k.address).collect();
+            let results = get_v2_pools(&batcher, &addresses).await;
+
+            // pairs whose dex has no init code hash are checked with a `getPair` call,
+            // the whole batch is checked at once
+            let calls = batch.iter().zip(results).map(|(key, result)| async {
+                let (reserves, token0, token1, factory) = result?;
+                let config = V2Config {
+                    name: "".to_string(),
+                    fee: ctx
+                        .v2_fee(chain_id, factory)
+                        .unwrap_or(U24::from(DEFAULT_V2_FEE)),
+                    token0,
+                    token1,
+                };
+                let state = V2State {
+                    r0: reserves.reserve0.to::<u128>(),
+                    r1: reserves.reserve1.to::<u128>(),
+                };
+                let provenance = verify_v2(
+                    ctx,
+                    chain_id,
+                    provider.clone(),
+                    key.address,
+                    factory,
+                    token0,
+                    token1,
+                )
+                .await;
+                Some((config, state, provenance))
+            });
+            let results = join_all(calls).await;
+
+            for (key, result) in batch.iter().zip(results) {
+                let Some((config, state, provenance)) = result else {
+                    println!("v2 pool {:?} fetch failed, requeueing it", key);
+                    ctx.retry_v2(key.clone());
+                    continue;
+                };
+                ctx.fill_v2_pool(key.clone(), config, state, provenance, synced_at);
+            }
+        }
+    }
+}
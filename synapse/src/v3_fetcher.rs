@@ -0,0 +1,230 @@
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use alloy::primitives::{
+    Address, U256,
+    aliases::{I24, U24},
+};
+use futures::future::join_all;
+use shape::{
+    id_address::IdAddress,
+    p_config::V3Config,
+    p_state::V3State,
+    p_ticks::{PoolWords, TicksBitMap},
+};
+use v3::v3_base::ticks::{Tick, Ticks};
+
+use crate::{
+    WsProvider,
+    calls::{
+        DEFAULT_MULTICALL_BATCH, MulticallBatcher, get_sync_point, get_v3_pools, get_v3_words,
+        pinned_block,
+    },
+    master_context::MasterContext,
+    provenance::verify_v3,
+    tick_window::{TickWindow, V3Words},
+};
+
+/// Fees are in hundredths of a bip, a pool can't take the whole input
+const MAX_FEE: u32 = 1_000_000;
+
+/// Drains `MasterContext::v3_hydrate_queue`, fetching config, state and the tick bitmap
+/// words around the current tick of the queued pools in batches per chain
+pub struct V3Fetcher {
+    pub batch_size: usize,
+    pub interval: Duration,
+    /// calls per Multicall3 request
+    pub multicall_batch: usize,
+    /// bitmap words loaded around the current tick
+    pub window: TickWindow,
+}
+
+impl Default for V3Fetcher {
+    fn default() -> Self {
+        Self {
+            batch_size: 100,
+            interval: Duration::from_secs(1),
+            multicall_batch: DEFAULT_MULTICALL_BATCH,
+            window: TickWindow::default(),
+        }
+    }
+}
+
+impl V3Fetcher {
+    pub async fn run(self, ctx: Arc<MasterContext>) {
+        loop {
+            let queued = ctx.take_v3_queue();
+            if queued.is_empty() {
+                tokio::time::sleep(self.interval).await;
+                continue;
+            }
+
+            let mut by_chain = BTreeMap::<u64, Vec<IdAddress>>::new();
+            for key in queued {
+                by_chain.entry(key.id).or_default().push(key);
+            }
+
+            for (chain_id, pools) in by_chain {
+                self.fetch_chain(&ctx, chain_id, pools).await;
+            }
+        }
+    }
+
+    async fn fetch_chain(&self, ctx: &MasterContext, chain_id: u64, pools: Vec<IdAddress>) {
+        let Some(provider) = ctx.provider(chain_id) else {
+            println!("no provider for chain {}, requeueing v3 pools", chain_id);
+            pools.into_iter().for_each(|k| ctx.enqueue_v3(k));
+            return;
+        };
+
+        for batch in pools.chunks(self.batch_size.max(1)) {
+            let Some(synced_at) = get_sync_point(&provider).await else {
+                println!("chain {} head lookup failed, requeueing v3 pools", chain_id);
+                batch.iter().for_each(|k| ctx.enqueue_v3(k.clone()));
+                continue;
+            };
+            let batcher = MulticallBatcher::new(provider.clone())
+                .batch_size(self.multicall_batch)
+                .at_block(pinned_block(&synced_at));
+            let fetched = self.fetch_batch(&batcher, batch).await;
+            let calls = batch.iter().zip(fetched).map(|(key, result)| async {
+                let (config, state, words, factory) = result?;
+                let provenance = verify_v3(
+                    ctx,
+                    chain_id,
+                    provider.clone(),
+                    key.address,
+                    factory,
+                    &config,
+                )
+                .await;
+                Some((config, state, words, provenance))
+            });
+            let results = join_all(calls).await;
+
+            for (key, result) in batch.iter().zip(results) {
+                let Some((config, state, words, provenance)) = result else {
+                    println!("v3 pool {:?} fetch failed, requeueing it", key);
+                    ctx.retry_v3(key.clone());
+                    continue;
+                };
+                ctx.fill_v3_pool(key.clone(), config, state, words, provenance, synced_at);
+            }
+        }
+    }
+
+    /// Pools of a batch and the tick window around their current tick, every read of the
+    /// batch going through `batcher` so they all come from the block it is pinned to
+    async fn fetch_batch(
+        &self,
+        batcher: &MulticallBatcher<WsProvider>,
+        batch: &[IdAddress],
+    ) -> Vec<Option<(V3Config, V3State, PoolWords, Address)>> {
+        let addresses: Vec<Address> = batch.iter().map(|k| k.address).collect();
+        // any contract can emit the Swap topic, a config no pool can have fails the
+        // fetch before it gets anywhere near the tick math
+        let pools: Vec<_> = get_v3_pools(batcher, &addresses)
+            .await
+            .into_iter()
+            .map(|pool| {
+                pool.filter(|(_, _, _, _, fee, tick_spacing, _)| {
+                    *tick_spacing > I24::ZERO && *fee < U24::from(MAX_FEE)
+                })
+            })
+            .collect();
+
+        let mut requests = Vec::new();
+        let positions: Vec<Vec<i16>> = addresses
+            .iter()
+            .zip(&pools)
+            .map(|(address, pool)| {
+                let Some((slot0, _, _, _, _, tick_spacing, _)) = pool else {
+                    return Vec::new();
+                };
+                let positions = self.window.initial_positions(slot0.tick, *tick_spacing);
+                requests.extend(positions.iter().map(|pos| (*address, *pos, *tick_spacing)));
+                positions
+            })
+            .collect();
+        let mut words = get_v3_words(batcher, &requests).await.into_iter();
+
+        let mut fetched: Vec<_> = pools
+            .into_iter()
+            .zip(positions)
+            .map(|(pool, positions)| {
+                let (slot0, liquidity, token0, token1, fee, tick_spacing, factory) = pool?;
+
+                let mut pool_words = PoolWords::default();
+                let mut complete = true;
+                for pos in positions {
+                    // a missing word would make the simulator see an empty range, so
+                    // the pool is only considered hydrated once every word is there
+                    match words.next().flatten() {
+                        Some(word) => {
+                            pool_words.words.insert(pos, word);
+                        }
+                        None => complete = false,
+                    }
+                }
+                if !complete {
+                    return None;
+                }
+
+                let config = V3Config {
+                    name: "".to_string(),
+                    fee,
+                    tick_spacing,
+                    token0,
+                    token1,
+                };
+                let state = V3State {
+                    tick: slot0.tick,
+                    x96price: slot0.sqrtPriceX96,
+                    liquidity,
+                };
+                Some((config, state, pool_words, factory))
+            })
+            .collect();
+
+        // a depth window keeps growing from the words already read, at the same block
+        let deepen = fetched
+            .iter_mut()
+            .zip(addresses)
+            .filter_map(|(pool, address)| {
+                let (config, state, words, _) = pool.as_mut()?;
+                let source = V3Words {
+                    batcher: batcher.clone(),
+                    pool: address,
+                    tick_spacing: config.tick_spacing,
+                };
+                Some(async move { self.window.deepen(&source, words, state.tick).await })
+            });
+        join_all(deepen).await;
+        fetched
+    }
+}
+
+/// Builds the `v3` simulator state out of the cached pool state and tick words
+pub fn to_trade_state(
+    state: &V3State,
+    words: &PoolWords,
+    tick_spacing: I24,
+) -> v3::v3_base::v3_state::V3State {
+    let ticks = words
+        .words
+        .values()
+        .flat_map(|w: &TicksBitMap| w.ticks.iter())
+        .map(|(tick, data)| Tick {
+            tick: *tick,
+            liquidity_net: data.liquidity_net,
+        })
+        .collect();
+
+    v3::v3_base::v3_state::V3State {
+        tick: state.tick,
+        ticks: Ticks::new(ticks),
+        liquidity: U256::from(state.liquidity),
+        x96price: U256::from(state.x96price),
+        tick_spacing,
+        words: words.words.keys().copied().collect(),
+    }
+}
@@ -1,207 +1,315 @@
 use std::{
-    collections::HashMap,
-    sync::{Arc, RwLock},
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
+use crate::{
+    calls::{
+        MulticallBatcher, get_sync_point, get_v4_initialize_key, get_v4_key, get_v4_liquidity,
+        get_v4_slot0, pinned_block,
+    },
+    tick_window::{TickWindow, V4Words},
+};
 use all_sol_types::sol_types::{
-    IPoolManager::IPoolManagerInstance, IPositionManager::IPositionManagerInstance,
+    IPoolManager::IPoolManagerInstance, IPositionManager::IPositionManagerInstance, PoolKey,
     StateView::StateViewInstance,
 };
-use alloy::{primitives::B256, providers::Provider, rpc::types::Log};
-use chains_json::{chain_json_model::DexJsonModel, chains::ChainsJsonInput};
+use alloy::{
+    eips::BlockId,
+    primitives::{Address, B256, aliases::I24},
+    providers::Provider,
+};
+use chains_json::chain_json_model::DexJsonModel;
 use cortex::{cortex::WsProvider, types::PartialV4Pool};
-use dashmap::DashMap;
-use futures::{SinkExt, channel::mpsc::Receiver, executor::block_on, future, lock::Mutex};
-use shape::{id_address::IdKey, p_config::V4Config, p_state::V3State};
+use dashmap::{DashMap, DashSet};
+use futures::{
+    StreamExt,
+    channel::mpsc::{Receiver, Sender},
+    future::join,
+};
+use shape::{id_address::IdKey, p_config::V4Config, p_state::V3State, p_ticks::PoolWords};
 
-use crate::calls::get_v4_key;
+/// How long a pool neither the position manager nor the pool manager knows is skipped
+const NOT_FOUND_TTL: Duration = Duration::from_secs(600);
 
+/// Resolves the config of v4 pools seen in swaps, in the background.
+///
+/// The pool manager only emits the pool id, the `PoolKey` behind it comes from
+/// `IPositionManager::poolKeys` or the `Initialize` log of the pool, the current state
+/// and tick words from the `StateView`.
 pub struct V4Fetcher {
     contracts: DashMap<u64, V4Contracts<WsProvider>>,
-    pools: DashMap<IdKey, PartialV4Pool>,
-    not_found: Arc<RwLock<Vec<IdKey>>>,
-    sender: Arc<Mutex<futures::channel::mpsc::Sender<V4FetchArgs>>>,
+    pools: Arc<DashMap<IdKey, PartialV4Pool>>,
+    /// pools requested and not fetched yet, so a busy pool is only fetched once
+    pending: DashSet<IdKey>,
+    /// pools whose key couldn't be found and until when they are skipped, lookups that
+    /// failed aren't recorded here
+    not_found: DashMap<IdKey, Instant>,
+    sender: Sender<V4FetchArgs>,
+    receiver: std::sync::Mutex<Option<Receiver<V4FetchArgs>>>,
+    /// pools fetched at the same time by `run`
+    concurrency: usize,
+    /// bitmap words loaded around the current tick
+    window: TickWindow,
 }
 
 impl V4Fetcher {
-    async fn new(data: ChainsJsonInput) -> Self {
+    pub fn new(pools: Arc<DashMap<IdKey, PartialV4Pool>>, window: TickWindow) -> Self {
         let (tx, rx) = futures::channel::mpsc::channel(10000);
 
-        let mut providers: HashMap<u64, String>;
-
-        let contracts = DashMap::<u64, V4Contracts<WsProvider>>::new();
-
-        for (chain_id, dta) in data.chains {
-            chaing_provider = 
-            let v4_dex_contracts = dta.dexes.iter().map(|dex| match dex {
-                DexJsonModel::V2 {
-                    address,
-                    fee,
-                    stable_fee,
-                } => (),
-                DexJsonModel::V3 { address, fee } => (),
-                DexJsonModel::V4 {
-                    state_view,
-                    pool_manager,
-                    postion_descriptor,
-                    position_manager,
-                    quoter,
-                    universal_router,
-                    permit2,
-                } => {
-                    let contracts: V4Contracts<WsProvider> = V4Contracts {
-                        state_view: StateViewInstance::new(state_view, pro)                   position_manager: todo!(),
-                        pools_manager: todo!(),
-                    };
-                }
-            });
+        Self {
+            contracts: DashMap::new(),
+            pools,
+            pending: DashSet::new(),
+            not_found: DashMap::new(),
+            sender: tx,
+            receiver: std::sync::Mutex::new(Some(rx)),
+            concurrency: 16,
+            window,
         }
-        let mut s = Self {
-            contracts,
-            pools: todo!(),
-            not_found: todo!(),
-            sender: todo!(),
-        };
+    }
 
-        for (id, chain) in data.chains {
-            chain.dexes.iter().map(|dex| {});
-        }
+    pub fn add_contracts(&self, chain_id: u64, contracts: V4Contracts<WsProvider>) {
+        self.contracts.insert(chain_id, contracts);
+    }
 
-        let worker = std::thread::spawn(move || {
-            Self::blocking_receive(&s, rx);
-        });
+    /// Queues a pool for the worker, pools already queued or recently found missing are
+    /// ignored
+    pub fn request(&self, args: V4FetchArgs) {
+        let key = IdKey {
+            id: args.chain,
+            key: args.id,
+        };
+        let skipped = self
+            .not_found
+            .get(&key)
+            .is_some_and(|until| *until > Instant::now());
+        if skipped || !self.pending.insert(key.clone()) {
+            return;
+        }
 
-        s
+        if let Err(err) = self.sender.clone().try_send(args) {
+            println!("error sending args to fetch worker {:?}", err);
+            self.pending.remove(&key);
+        }
     }
 
-    async fn update_v4(
-        &mut self,
-        dex: chains_json::chain_json_model::DexJsonModel,
-    ) -> Option<chains_json::chain_json_model::DexJsonModel> {
-        match dex {
-            chains_json::chain_json_model::DexJsonModel::V2 {
-                address,
-                fee,
-                stable_fee,
-            } => return None,
-            chains_json::chain_json_model::DexJsonModel::V3 { address, fee } => {
-                todo!()
-            }
-            chains_json::chain_json_model::DexJsonModel::V4 {
-                state_view,
-                pool_manager,
-                postion_descriptor,
-                position_manager,
-                quoter,
-                universal_router,
-                permit2,
-            } => {
-                return Some(DexJsonModel::V4 {
-                    state_view: state_view,
-                    pool_manager: pool_manager,
-                    postion_descriptor: postion_descriptor,
-                    position_manager: position_manager,
-                    quoter: quoter,
-                    universal_router: universal_router,
-                    permit2: permit2,
-                });
-            }
+    /// Fetches the requested pools until the context is dropped, only the first call
+    /// gets the receiver
+    pub async fn run(self: Arc<Self>) {
+        let Some(rx) = self.receiver.lock().ok().and_then(|mut r| r.take()) else {
+            println!("v4 fetch worker already running");
+            return;
         };
+
+        rx.for_each_concurrent(self.concurrency, |args| self.fetch_and_update(args))
+            .await;
     }
 
     async fn fetch_and_update(&self, args: V4FetchArgs) {
-        let (id, chain) = (args.id, args.chain);
-        if let Some(v4_contracts) = self.contracts.get(&chain) {
-            let pos_man = v4_contracts.value().position_manager.clone();
-            if let Ok(key) = get_v4_key(id, pos_man).await {
-                let ik = IdKey { id: chain, key: id };
-                self.pools
-                    .entry(ik)
-                    .and_modify(|x| x.config = Some(key.clone().into()))
-                    .or_insert_with(|| PartialV4Pool {
-                        chain,
-                        state_view: v4_contracts.state_view.address().clone(),
-                        config: Some(key.into()),
-                        state: None,
-                    });
-            }
+        let ik = IdKey {
+            id: args.chain,
+            key: args.id,
         };
+        self.fetch(&ik).await;
+        self.pending.remove(&ik);
     }
 
-    pub fn spaw_fetch_worker(&self) {}
-
-    fn blocking_receive(&self, mut rx: Receiver<V4FetchArgs>) {
-        block_on(async move {
-            while let Ok(r) = rx.recv().await {
-                let Some(v4_contracts) = self.contracts.get(&r.chain) else {
-                    continue;
-                };
-
-                let state_view = *v4_contracts.state_view.address();
-                let log = r.log.inner;
-
-                let Some(v4_contracts) = self.contracts.get(&r.chain) else {
-                    continue;
-                };
-
-                let Ok(call) = get_v4_key(r.id, v4_contracts.position_manager.clone()).await else {
-                    continue;
-                };
-
-                let config = V4Config {
-                    fee: call.fee,
-                    tick_spacing: call.tickSpacing,
-                    hooks: call.hooks,
-                    token0: call.currency0,
-                    token1: call.currency1,
-                };
-
-                let state = V3State {
-                    tick: log.tick,
-                    x96price: log.sqrtPriceX96,
-                    liquidity: log.liquidity,
-                };
-
-                PartialV4Pool {
-                    chain: r.chain,
-                    state_view,
-                    config: Some(config),
-                    state: Some(state),
-                };
-            }
-        });
-    }
+    /// Resolves the config of the pool unless an `Initialize` log already brought it,
+    /// then its state and the words around the current tick, all read at one block
+    async fn fetch(&self, ik: &IdKey) {
+        let (id, chain) = (ik.key, ik.id);
+        let known = self
+            .pools
+            .get(ik)
+            .map(|p| (p.config.clone(), p.state.is_some() && p.words.is_some()));
+        if let Some((Some(_), true)) = known {
+            return;
+        }
 
-    pub async fn handle_v4_swap(&self, args: V4FetchArgs, chain_id: u64) {
-        let log: Log<all_sol_types::sol_types::IPoolManager::Swap> = args.log;
-        let key = IdKey {
-            id: chain_id,
-            key: log.inner.data.id,
+        let Some(v4_contracts) = self.contracts.get(&chain).map(|c| c.clone()) else {
+            return;
+        };
+        let provider = v4_contracts.state_view.provider().clone();
+        let Some(synced_at) = get_sync_point(&provider).await else {
+            println!(
+                "chain {} head lookup failed, dropping v4 pool {:?}",
+                chain, ik
+            );
+            return;
         };
+        let block = pinned_block(&synced_at);
 
-        let args = V4FetchArgs {
-            id: log.inner.id,
-            chain: chain_id,
-            log: log,
+        let config = match known.and_then(|(config, _)| config) {
+            Some(config) => config,
+            None => match self.resolve_config(ik, &v4_contracts, block).await {
+                Some(config) => config,
+                None => return,
+            },
         };
 
-        //ideal is to make args come back on Err(err) and them print
-        println!("sending args: {:?}", &args);
-        if let Err(err) = self.sender.lock().await.send(args).await {
-            println!("error sending args to fetch worker {:?}", err);
+        let (slot0, liquidity) = join(
+            get_v4_slot0(v4_contracts.state_view.clone(), id, block),
+            get_v4_liquidity(v4_contracts.state_view.clone(), id, block),
+        )
+        .await;
+        let state = slot0.zip(liquidity).map(|(slot0, liquidity)| V3State {
+            tick: slot0.tick,
+            x96price: slot0.sqrtPriceX96,
+            liquidity,
+        });
+        let words = match &state {
+            Some(state) => {
+                self.fetch_words(&provider, &v4_contracts, id, state, &config, block)
+                    .await
+            }
+            None => None,
+        };
+
+        // a swap after the pinned block applied while the fetch was in flight is more
+        // recent than the fetched state
+        self.pools
+            .entry(ik.clone())
+            .and_modify(|x| {
+                x.config = Some(config.clone());
+                let newer = state.is_some()
+                    && (x.state.is_none() || synced_at.supersedes(x.synced_at.as_ref()));
+                if newer {
+                    x.state = state.clone();
+                    x.synced_at = Some(synced_at);
+                }
+                match (x.words.as_mut(), &words) {
+                    (Some(current), Some(words)) if newer => {
+                        current.words.extend(words.words.clone())
+                    }
+                    (Some(current), Some(words)) => {
+                        for (pos, word) in &words.words {
+                            current.words.entry(*pos).or_insert_with(|| word.clone());
+                        }
+                    }
+                    (None, _) => x.words = words.clone(),
+                    (Some(_), None) => {}
+                }
+            })
+            .or_insert_with(|| PartialV4Pool {
+                chain,
+                state_view: *v4_contracts.state_view.address(),
+                config: Some(config),
+                state,
+                words,
+                synced_at: Some(synced_at),
+            });
+    }
+
+    /// Config of a pool seen before its `Initialize` log, from the position manager or,
+    /// for pools never minted through it, the `Initialize` log itself. A pool neither
+    /// knows is skipped for `NOT_FOUND_TTL`, a failed log lookup is asked again by the
+    /// next swap
+    async fn resolve_config(
+        &self,
+        ik: &IdKey,
+        v4_contracts: &V4Contracts<WsProvider>,
+        block: BlockId,
+    ) -> Option<V4Config> {
+        let id = ik.key;
+        // `poolKeys` is a mapping, an unknown id reads as a zeroed key
+        match get_v4_key(id, v4_contracts.position_manager.clone(), block).await {
+            Ok(key) => {
+                if let Some(config) = checked_config(id, key) {
+                    return Some(config);
+                }
+            }
+            Err(err) => println!("v4 pool {:?} position manager lookup failed: {:?}", ik, err),
+        }
+
+        match get_v4_initialize_key(&v4_contracts.pools_manager, id).await {
+            Ok(key) => {
+                if let Some(config) = key.and_then(|key| checked_config(id, key)) {
+                    return Some(config);
+                }
+            }
+            Err(err) => {
+                println!("v4 pool {:?} initialize lookup failed: {:?}", ik, err);
+                return None;
+            }
         }
+
+        println!(
+            "v4 pool {:?} not found, skipping it for {:?}",
+            ik, NOT_FOUND_TTL
+        );
+        let now = Instant::now();
+        self.not_found.retain(|_, until| *until > now);
+        self.not_found.insert(ik.clone(), now + NOT_FOUND_TTL);
+        None
+    }
+
+    /// Tick window around the current tick, `None` unless every word of it could be read
+    async fn fetch_words(
+        &self,
+        provider: &WsProvider,
+        v4_contracts: &V4Contracts<WsProvider>,
+        id: B256,
+        state: &V3State,
+        config: &V4Config,
+        block: BlockId,
+    ) -> Option<PoolWords> {
+        let source = V4Words {
+            batcher: MulticallBatcher::new(provider.clone()).at_block(block),
+            state_view: *v4_contracts.state_view.address(),
+            id,
+            tick_spacing: config.tick_spacing,
+        };
+        self.window.load(&source, state.tick).await
     }
 }
 
+/// Config of the `PoolKey` read for `id`, `None` unless it hashes back to the id and
+/// has a tick spacing the tick math can work with
+fn checked_config(id: B256, key: PoolKey) -> Option<V4Config> {
+    let config = V4Config::from(key);
+    (config.pool_id() == id && config.tick_spacing > I24::ZERO).then_some(config)
+}
+
 #[derive(Debug)]
 pub struct V4FetchArgs {
     pub id: B256,
     pub chain: u64,
-    pub log: Log<all_sol_types::sol_types::IPoolManager::Swap>,
 }
 
+#[derive(Clone)]
 pub struct V4Contracts<P: Provider + Clone + Send + Sync> {
     pub state_view: StateViewInstance<P>,
     pub position_manager: IPositionManagerInstance<P>,
     pub pools_manager: IPoolManagerInstance<P>,
 }
+
+impl<P: Provider + Clone + Send + Sync> V4Contracts<P> {
+    /// Builds the v4 contract instances of a dex entry, `None` for v2/v3 dexes
+    pub fn from_dex(dex: &DexJsonModel, provider: P) -> Option<Self> {
+        let DexJsonModel::V4 {
+            state_view,
+            pool_manager,
+            position_manager,
+            ..
+        } = dex
+        else {
+            return None;
+        };
+
+        let (Ok(state_view), Ok(pool_manager), Ok(position_manager)) = (
+            Address::from_str(state_view),
+            Address::from_str(pool_manager),
+            Address::from_str(position_manager),
+        ) else {
+            return None;
+        };
+
+        Some(Self {
+            state_view: StateViewInstance::new(state_view, provider.clone()),
+            position_manager: IPositionManagerInstance::new(position_manager, provider.clone()),
+            pools_manager: IPoolManagerInstance::new(pool_manager, provider),
+        })
+    }
+}
@@ -0,0 +1,213 @@
+use std::{str::FromStr, sync::Arc, time::Duration};
+
+use alloy::{providers::Provider, rpc::types::Filter, transports::http::reqwest::Url};
+use chains_json::chain_json_model::DexJsonModel;
+use futures::channel::{mpsc::UnboundedSender, oneshot};
+
+use crate::{
+    WsProvider, decode_logs_listener_blocking, master_context::MasterContext, watched_events,
+    ws_provider,
+};
+
+/// Block range (inclusive) whose logs were missed while a chain subscription was down
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockGap {
+    pub chain: u64,
+    pub from: u64,
+    pub to: u64,
+}
+
+/// A gap for the backfiller, `done` fires once every log of it was applied
+#[derive(Debug)]
+pub struct GapRequest {
+    pub gap: BlockGap,
+    pub done: Option<oneshot::Sender<()>>,
+    /// times the rest of the gap was requeued after a block kept failing
+    pub retries: u32,
+}
+
+/// Gap of a chain whose backfill the live logs wait for
+#[derive(Debug)]
+pub struct PendingGap {
+    /// last block the backfill applies
+    pub to: u64,
+    pub done: oneshot::Receiver<()>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    current: Duration,
+    min: Duration,
+    max: Duration,
+}
+
+impl Backoff {
+    pub fn new(min: Duration, max: Duration) -> Self {
+        Self {
+            current: min,
+            min,
+            max,
+        }
+    }
+
+    /// Returns the time to wait before the next attempt and doubles it for the one after
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = self.current.saturating_mul(2).min(self.max);
+        delay
+    }
+
+    pub fn reset(&mut self) {
+        self.current = self.min;
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SupervisorConfig {
+    pub min_backoff: Duration,
+    pub max_backoff: Duration,
+    /// a subscription that delivers nothing for this long is considered dropped
+    pub idle_timeout: Duration,
+    /// blocks before the head to backfill when the chain has never been processed
+    pub startup_lookback: u64,
+}
+
+impl Default for SupervisorConfig {
+    fn default() -> Self {
+        Self {
+            min_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            idle_timeout: Duration::from_secs(120),
+            startup_lookback: 100,
+        }
+    }
+}
+
+/// Keeps the pool logs subscription of a chain alive forever.
+///
+/// Every time the subscription drops it reconnects with exponential backoff, rotating
+/// through all the ws nodes of the chain, resubscribes with the same filter and sends
+/// the block range missed in between to `gaps`. The first subscription reports the
+/// last `startup_lookback` blocks instead.
+///
+/// The backoff only goes back to its minimum once a subscription delivered a log, so a
+/// node that accepts the subscription and drops it right away isn't reconnected to in
+/// a loop. A subscription that only went idle stays on the same node.
+///
+/// Live logs wait for the backfill of the gap, and a new gap is only computed once the
+/// previous backfill is done, so every block is applied once and in order.
+pub async fn supervise_chain(
+    chain_id: u64,
+    ws_urls: Vec<String>,
+    dexes: Vec<DexJsonModel>,
+    ctx: Arc<MasterContext>,
+    gaps: UnboundedSender<GapRequest>,
+    config: SupervisorConfig,
+) {
+    let filter = Filter::new().events(watched_events());
+    let mut backoff = Backoff::new(config.min_backoff, config.max_backoff);
+    let mut next_url = 0;
+    let mut pending: Option<PendingGap> = None;
+
+    loop {
+        if let Some(gap) = pending.take() {
+            let _ = gap.done.await;
+        }
+
+        let Some((url_idx, provider)) = connect_any(chain_id, &ws_urls, next_url).await else {
+            let delay = backoff.next_delay();
+            println!(
+                "chain {} has no reachable ws node, retrying in {:?}",
+                chain_id, delay
+            );
+            tokio::time::sleep(delay).await;
+            continue;
+        };
+        let mut ws = match provider.subscribe_logs(&filter).await {
+            Ok(ws) => ws,
+            Err(err) => {
+                next_url = url_idx + 1;
+                let delay = backoff.next_delay();
+                println!(
+                    "chain {} log subscription failed: {:?}, retrying in {:?}",
+                    chain_id, err, delay
+                );
+                tokio::time::sleep(delay).await;
+                continue;
+            }
+        };
+        ctx.add_chain(chain_id, provider.clone(), &dexes);
+
+        match provider.get_block_number().await {
+            Ok(head) => {
+                let from = match ctx.last_block(chain_id) {
+                    Some(last) => last + 1,
+                    None => (head + 1).saturating_sub(config.startup_lookback),
+                };
+
+                if from <= head {
+                    let gap = BlockGap {
+                        chain: chain_id,
+                        from,
+                        to: head,
+                    };
+                    println!("chain {} missed blocks {}..={}", chain_id, gap.from, gap.to);
+                    let (done, done_rx) = oneshot::channel();
+                    let request = GapRequest {
+                        gap,
+                        done: Some(done),
+                        retries: 0,
+                    };
+                    match gaps.unbounded_send(request) {
+                        Ok(()) => {
+                            pending = Some(PendingGap {
+                                to: gap.to,
+                                done: done_rx,
+                            })
+                        }
+                        Err(err) => println!("error reporting block gap {:?}", err),
+                    }
+                }
+            }
+            Err(err) => println!("chain {} head lookup failed: {:?}", chain_id, err),
+        }
+
+        let end = decode_logs_listener_blocking(
+            chain_id,
+            &mut ws,
+            &ctx,
+            config.idle_timeout,
+            &mut pending,
+        )
+        .await;
+        if end.logs > 0 {
+            backoff.reset();
+        }
+        // a node that dropped us goes to the back of the line
+        next_url = if end.idle { url_idx } else { url_idx + 1 };
+
+        let delay = backoff.next_delay();
+        println!(
+            "chain {} subscription dropped after {} logs, reconnecting in {:?}",
+            chain_id, end.logs, delay
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// Tries every url once, starting at `start`, and returns the first that connects
+async fn connect_any(chain_id: u64, urls: &[String], start: usize) -> Option<(usize, WsProvider)> {
+    for offset in 0..urls.len() {
+        let idx = (start + offset) % urls.len();
+        let Ok(url) = Url::from_str(&urls[idx]) else {
+            println!("invalid ws url for chain {}: {}", chain_id, urls[idx]);
+            continue;
+        };
+
+        match ws_provider(url).await {
+            Ok(provider) => return Some((idx, provider)),
+            Err(err) => println!("ws provider creation failed: {:?}", err),
+        }
+    }
+    None
+}